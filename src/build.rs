@@ -0,0 +1,102 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+};
+
+use crossbeam_channel::{unbounded, Sender};
+
+/// Output of the build or other command that synf runs, streamed to stderr
+/// and log file as it comes, with last lines kept to report them if it fails.
+pub(crate) struct BuildLog {
+    /// Prefix of lines printed to stderr, f.e "[build]".
+    prefix: &'static str,
+    file: Option<File>,
+    tail: VecDeque<String>,
+    tail_size: usize,
+}
+
+impl BuildLog {
+    /// Creates log, truncating log file if it is given.
+    pub(crate) fn new(prefix: &'static str, file: Option<&Path>, tail_size: usize) -> BuildLog {
+        let file = file.and_then(|path| match File::create(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Failed to create build log file {:?}: {}", path, e);
+                None
+            }
+        });
+        BuildLog {
+            prefix,
+            file,
+            tail: VecDeque::new(),
+            tail_size,
+        }
+    }
+
+    /// Runs command to completion, logging both its stdout and stderr.
+    pub(crate) fn run(&mut self, command: &mut Command) -> std::io::Result<ExitStatus> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (sender, receiver) = unbounded::<String>();
+        read_lines(child.stdout.take().unwrap(), sender.clone());
+        read_lines(child.stderr.take().unwrap(), sender);
+        // ends once both outputs are closed
+        for line in receiver.iter() {
+            self.line(&line);
+        }
+        child.wait()
+    }
+
+    fn line(&mut self, line: &str) {
+        eprintln!("{} {}", self.prefix, line);
+        if let Some(file) = &mut self.file {
+            if let Err(e) = writeln!(file, "{}", line) {
+                eprintln!("Failed to write build log file: {}", e);
+                self.file = None;
+            }
+        }
+        if self.tail_size > 0 {
+            if self.tail.len() == self.tail_size {
+                self.tail.pop_front();
+            }
+            self.tail.push_back(line.to_string());
+        }
+    }
+
+    /// Last lines of the build output.
+    pub(crate) fn tail(&self) -> Vec<String> {
+        self.tail.iter().cloned().collect()
+    }
+}
+
+fn read_lines(output: impl Read + Send + 'static, sender: Sender<String>) {
+    thread::spawn(move || {
+        let mut output = BufReader::new(output);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match output.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // compilers do not always print valid UTF-8
+                    let line = String::from_utf8_lossy(&line);
+                    if sender.send(line.trim_end().to_string()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to read build output: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
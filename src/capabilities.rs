@@ -0,0 +1,54 @@
+use serde_json::Value;
+
+/// Extracts capabilities that server declared in its initialize response.
+pub(crate) fn from_initialize_response(response: &str) -> Option<Value> {
+    let response = serde_json::from_str::<Value>(response).ok()?;
+    response.get("result")?.get("capabilities").cloned()
+}
+
+/// Checks if capability is declared, unknown capabilities are assumed to declare everything.
+pub(crate) fn declares(capabilities: Option<&Value>, name: &str) -> bool {
+    match capabilities {
+        Some(capabilities) => capabilities
+            .get(name)
+            .is_some_and(|capability| !capability.is_null()),
+        None => true,
+    }
+}
+
+/// Checks if capability is declared with `listChanged`, which servers have to set
+/// to send list_changed notifications, unknown capabilities are assumed to declare everything.
+pub(crate) fn declares_list_changed(capabilities: Option<&Value>, name: &str) -> bool {
+    match capabilities {
+        Some(capabilities) => capabilities
+            .get(name)
+            .and_then(|capability| capability.get("listChanged"))
+            .is_some_and(|list_changed| list_changed == true),
+        None => true,
+    }
+}
+
+/// Describes how current capabilities differ from original ones.
+pub(crate) fn differences(original: &Value, current: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let original = original.as_object().unwrap_or(&empty);
+    let current = current.as_object().unwrap_or(&empty);
+
+    let mut differences = Vec::new();
+    for (name, capability) in original {
+        match current.get(name) {
+            None => differences.push(format!("'{}' is no longer declared", name)),
+            Some(current) if current != capability => differences.push(format!(
+                "'{}' changed from {} to {}",
+                name, capability, current
+            )),
+            _ => {}
+        }
+    }
+    for name in current.keys() {
+        if !original.contains_key(name) {
+            differences.push(format!("'{}' is newly declared", name));
+        }
+    }
+    differences
+}
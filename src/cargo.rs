@@ -0,0 +1,128 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::Context;
+use serde::Deserialize;
+
+/// Output of `cargo metadata --no-deps`, which only lists workspace members as packages.
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    target_directory: PathBuf,
+    workspace_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl Package {
+    fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(Path::new(""))
+    }
+
+    fn binaries(&self) -> impl Iterator<Item = &str> {
+        self.targets
+            .iter()
+            .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+            .map(|target| target.name.as_str())
+    }
+}
+
+fn metadata(project: &Path) -> eyre::Result<Metadata> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(project)
+        .output()
+        .context("failed to run cargo metadata")?;
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "cargo metadata failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    serde_json::from_slice(&output.stdout).context("failed to parse cargo metadata")
+}
+
+/// Finds debug binary that `cargo build` makes, which is the binary of the package
+/// in project folder or the only binary among workspace members.
+pub(crate) fn binary(project: &Path) -> eyre::Result<PathBuf> {
+    let metadata = metadata(project)?;
+    let project = project
+        .canonicalize()
+        .unwrap_or_else(|_| project.to_path_buf());
+
+    let own: Vec<&str> = metadata
+        .packages
+        .iter()
+        .filter(|package| package.dir() == project)
+        .flat_map(|package| {
+            let binaries: Vec<&str> = package.binaries().collect();
+            // package with several binaries runs the one named as the package
+            match binaries.iter().find(|name| **name == package.name) {
+                Some(name) => vec![*name],
+                None => binaries,
+            }
+        })
+        .collect();
+    let binaries = if own.is_empty() {
+        metadata
+            .packages
+            .iter()
+            .flat_map(Package::binaries)
+            .collect()
+    } else {
+        own
+    };
+
+    match binaries.as_slice() {
+        [binary] => {
+            let file = format!("{}{}", binary, std::env::consts::EXE_SUFFIX);
+            Ok(metadata.target_directory.join("debug").join(file))
+        }
+        [] => Err(eyre::eyre!("no binary targets found in {:?}", project)),
+        _ => Err(eyre::eyre!(
+            "several binary targets found in {:?}: {}, set command in [run] to choose one",
+            project,
+            binaries.join(", ")
+        )),
+    }
+}
+
+/// Paths relative to project that can change the build of workspace members in it.
+pub(crate) fn watch_paths(project: &Path) -> eyre::Result<Vec<String>> {
+    let metadata = metadata(project)?;
+    let project = project
+        .canonicalize()
+        .unwrap_or_else(|_| project.to_path_buf());
+
+    let mut dirs: Vec<&Path> = metadata.packages.iter().map(Package::dir).collect();
+    // virtual workspace manifest is not a package of its own
+    dirs.push(&metadata.workspace_root);
+
+    let mut paths = Vec::new();
+    for dir in dirs {
+        let Ok(dir) = dir.strip_prefix(&project) else {
+            continue;
+        };
+        for name in ["src", "Cargo.toml", "build.rs"] {
+            let path = dir.join(name).to_string_lossy().replace('\\', "/");
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    Ok(paths)
+}
@@ -0,0 +1,54 @@
+use std::{io::stdin, sync::Arc, thread};
+
+use crossbeam_channel::{unbounded, Receiver};
+
+/// Receives messages that synf sends to MCP client.
+pub(crate) trait ClientSink: Send + Sync {
+    /// Sends single JSON-RPC message to client.
+    fn send(&self, message: &str);
+}
+
+/// Transport used to talk with MCP client.
+pub(crate) struct Client {
+    /// Messages received from client, one JSON-RPC message per item.
+    pub(crate) messages: Receiver<String>,
+    pub(crate) sink: Arc<dyn ClientSink>,
+    /// Disconnected once client has closed connection and synf should exit.
+    pub(crate) disconnected: Receiver<()>,
+}
+
+struct Stdout;
+
+impl ClientSink for Stdout {
+    fn send(&self, message: &str) {
+        println!("{}", message.trim_end());
+    }
+}
+
+/// Creates client transport talking to MCP client over stdio.
+pub(crate) fn stdio() -> Client {
+    let (sender, receiver) = unbounded::<String>();
+    let (disconnected_sender, disconnected) = unbounded::<()>();
+
+    thread::spawn(move || {
+        // dropped when stdin is closed
+        let _disconnected_sender = disconnected_sender;
+        for line in stdin().lines() {
+            match line {
+                Ok(line) => {
+                    sender.send(line).unwrap();
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    Client {
+        messages: receiver,
+        sink: Arc::new(Stdout),
+        disconnected,
+    }
+}
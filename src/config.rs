@@ -1,10 +1,10 @@
 use std::{
+    collections::BTreeMap,
     fmt::{Display, Formatter},
     path::Path,
 };
 
 use serde::Deserialize;
-use toml;
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub(crate) enum Language {
@@ -16,6 +16,22 @@ pub(crate) enum Language {
     Golang,
     #[serde(rename = "kotlin")]
     Kotlin,
+    #[serde(rename = "rust")]
+    Rust,
+    #[serde(rename = "java")]
+    Java,
+    #[serde(rename = "deno")]
+    Deno,
+    #[serde(rename = "bun")]
+    Bun,
+    #[serde(rename = "csharp")]
+    Csharp,
+    #[serde(rename = "ruby")]
+    Ruby,
+    #[serde(rename = "php")]
+    Php,
+    #[serde(rename = "elixir")]
+    Elixir,
 }
 
 impl Display for Language {
@@ -25,29 +41,183 @@ impl Display for Language {
             Language::Python => write!(f, "python"),
             Language::Golang => write!(f, "golang"),
             Language::Kotlin => write!(f, "kotlin"),
+            Language::Rust => write!(f, "rust"),
+            Language::Java => write!(f, "java"),
+            Language::Deno => write!(f, "deno"),
+            Language::Bun => write!(f, "bun"),
+            Language::Csharp => write!(f, "csharp"),
+            Language::Ruby => write!(f, "ruby"),
+            Language::Php => write!(f, "php"),
+            Language::Elixir => write!(f, "elixir"),
         }
     }
 }
 
+/// Transport that server uses to talk to synf.
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+pub(crate) enum Transport {
+    #[default]
+    #[serde(rename = "stdio")]
+    Stdio,
+    /// Streamable HTTP transport.
+    #[serde(rename = "http")]
+    Http,
+    /// Legacy HTTP with SSE transport.
+    #[serde(rename = "sse")]
+    Sse,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct Config {
     pub(crate) language: Language,
     pub(crate) watch: Option<Watch>,
     pub(crate) resend_resource_subscriptions: Option<bool>,
-    pub(crate) build: Option<CommandConfig>,
-    pub(crate) run: Option<CommandConfig>,
+    pub(crate) replay_in_flight_requests: Option<bool>,
+    /// How long to wait for responses to requests of disconnected client before exiting.
+    pub(crate) disconnect_grace_ms: Option<u64>,
+    pub(crate) build_gate: Option<bool>,
+    pub(crate) build: Option<BuildConfig>,
+    pub(crate) run: Option<RunConfig>,
+    /// Environment of both build and run commands.
+    #[serde(flatten)]
+    pub(crate) env: EnvConfig,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub(crate) struct CommandConfig {
     pub(crate) command: Option<String>,
     pub(crate) args: Option<Vec<String>>,
+    /// Directory to run command in, relative to project.
+    pub(crate) cwd: Option<String>,
+    /// Run command with args as a command line of platform shell.
+    pub(crate) shell: Option<bool>,
+}
+
+/// Environment variables to set for commands, in addition to the ones synf has.
+#[derive(Deserialize, Clone, Default)]
+pub(crate) struct EnvConfig {
+    /// Variables from dotenv file relative to project.
+    pub(crate) env_file: Option<String>,
+    /// Variables that override the ones from env_file, where ${VAR} is replaced
+    /// by variable from environment of synf.
+    pub(crate) env: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BuildConfig {
+    #[serde(flatten)]
+    pub(crate) command: CommandConfig,
+    #[serde(flatten)]
+    pub(crate) env: EnvConfig,
+    /// File to save output of the last build to, relative to project.
+    pub(crate) log_file: Option<String>,
+    /// How many last lines of failed build output to send to client.
+    pub(crate) failure_lines: Option<usize>,
+    /// Commands to run in sequence instead of the single command.
+    pub(crate) steps: Option<Vec<BuildStepConfig>>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BuildStepConfig {
+    pub(crate) name: String,
+    #[serde(flatten)]
+    pub(crate) command: CommandConfig,
+    /// Environment of the step, overriding environment of [build].
+    #[serde(flatten)]
+    pub(crate) env: EnvConfig,
+    /// Go on with the next steps even if this one fails.
+    pub(crate) continue_on_error: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RunConfig {
+    #[serde(flatten)]
+    pub(crate) command: CommandConfig,
+    #[serde(flatten)]
+    pub(crate) env: EnvConfig,
+    pub(crate) transport: Option<Transport>,
+    /// URL of server endpoint, required for http and sse transports.
+    pub(crate) url: Option<String>,
+    /// How long to wait for server to accept connections after start.
+    pub(crate) ready_timeout_ms: Option<u64>,
+    /// How long to wait for server to respond to initialize after start.
+    pub(crate) initialize_timeout_ms: Option<u64>,
+    /// How long to wait for server to exit on its own before terminating it.
+    pub(crate) shutdown_grace_ms: Option<u64>,
+    /// How long to wait for server to exit after SIGTERM before killing it.
+    pub(crate) kill_timeout_ms: Option<u64>,
+    /// Stop reading server stdout once it prints something that is not JSON-RPC message.
+    pub(crate) strict_stdout: Option<bool>,
+    /// Send warnings about stdout pollution to client as log messages too.
+    pub(crate) notify_stdout_pollution: Option<bool>,
+    /// Restart server when it exits on its own.
+    pub(crate) restart_on_crash: Option<bool>,
+    /// How many times in a row to restart server crashing shortly after start.
+    pub(crate) max_crash_restarts: Option<u32>,
+    /// Delay before the first restart of crashed server, doubled for every next one.
+    pub(crate) crash_backoff_ms: Option<u64>,
 }
 
 #[derive(Deserialize)]
 pub(crate) struct Watch {
     pub(crate) default_paths: Option<Vec<String>>,
     pub(crate) extra_paths: Option<Vec<String>>,
+    /// Glob patterns for paths relative to project, changes in which reload server.
+    pub(crate) include: Option<Vec<String>>,
+    /// Glob patterns for paths relative to project, changes in which are ignored.
+    pub(crate) exclude: Option<Vec<String>>,
+    /// Ignore changes in paths ignored by .gitignore and .ignore files.
+    pub(crate) gitignore: Option<bool>,
+    /// How long to wait for changes to settle before reloading.
+    pub(crate) debounce_ms: Option<u64>,
+    pub(crate) mode: Option<WatchMode>,
+    /// How often to check for changes in poll mode.
+    pub(crate) poll_interval_ms: Option<u64>,
+    pub(crate) rules: Option<Vec<WatchRule>>,
+}
+
+/// Action for changes in paths matching glob, instead of rebuilding server.
+#[derive(Deserialize, Clone)]
+pub(crate) struct WatchRule {
+    /// Glob pattern for paths relative to project.
+    pub(crate) glob: String,
+    pub(crate) action: WatchAction,
+    /// URI of resource for changed file, used by notify_resources action,
+    /// where {path} is replaced by path relative to project
+    /// and {absolute_path} by absolute one.
+    pub(crate) uri: Option<String>,
+    /// Command that is run by run action.
+    #[serde(flatten)]
+    pub(crate) command: CommandConfig,
+}
+
+/// What to do about changes, from the weakest to the strongest.
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) enum WatchAction {
+    /// Send notifications/resources/updated for changed files.
+    #[serde(rename = "notify_resources")]
+    NotifyResources,
+    /// Run command, f.e to regenerate something.
+    #[serde(rename = "run")]
+    Run,
+    /// Restart server without building it.
+    #[serde(rename = "restart")]
+    Restart,
+    /// Build and restart server.
+    #[serde(rename = "rebuild")]
+    Rebuild,
+}
+
+/// How changes in watched paths are detected.
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+pub(crate) enum WatchMode {
+    /// Notifications from OS, falling back to polling for paths that cannot be watched.
+    #[default]
+    #[serde(rename = "native")]
+    Native,
+    /// Periodic scanning, which works on network and container filesystems.
+    #[serde(rename = "poll")]
+    Poll,
 }
 
 pub(crate) fn read_from_toml(path: &Path) -> Result<Config, eyre::Report> {
@@ -0,0 +1,163 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::runner::{Reload, Runner};
+
+/// Name of the socket that `synf dev` listens on in project directory.
+pub(crate) const SOCKET_NAME: &str = ".synf.sock";
+
+fn socket_path(project: &Path) -> PathBuf {
+    project.join(SOCKET_NAME)
+}
+
+fn command(reload: Reload) -> &'static str {
+    match reload {
+        Reload::Full => "reload",
+        Reload::BuildOnly => "build",
+        Reload::RestartOnly => "restart",
+    }
+}
+
+fn parse_command(command: &str) -> Option<Reload> {
+    match command {
+        "reload" => Some(Reload::Full),
+        "build" => Some(Reload::BuildOnly),
+        "restart" => Some(Reload::RestartOnly),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use eyre::Context;
+
+    use super::{command, parse_command, socket_path};
+    use crate::runner::{Reload, Runner};
+
+    pub(super) fn listen(project: &Path, runner: Arc<Mutex<Runner>>) -> eyre::Result<()> {
+        let path = socket_path(project);
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(eyre::eyre!(
+                    "another synf dev is already listening on {:?}",
+                    path
+                ));
+            }
+            // left by synf that did not exit cleanly
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove stale socket {:?}", path))?;
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to listen on control socket {:?}", path))?;
+        eprintln!("Listening for reload requests on {:?}", path);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = handle(stream, &runner) {
+                            eprintln!("Failed to handle reload request: {:#}", e);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Control socket stopped accepting connections: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn handle(stream: UnixStream, runner: &Mutex<Runner>) -> eyre::Result<()> {
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        if line.is_empty() {
+            // connection only checked that socket is in use
+            return Ok(());
+        }
+        let mut stream = stream;
+        let Some(reload) = parse_command(line.trim()) else {
+            writeln!(stream, "error: unknown command {:?}", line.trim())?;
+            return Ok(());
+        };
+
+        eprintln!("Received {:?} request from control socket", reload);
+        let succeeded = runner.lock().unwrap().reload(reload);
+        if succeeded {
+            writeln!(stream, "ok")?;
+        } else {
+            writeln!(
+                stream,
+                "error: {} failed, see synf output for details",
+                command(reload)
+            )?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn send(project: &Path, reload: Reload) -> eyre::Result<()> {
+        let path = socket_path(project);
+        let mut stream = UnixStream::connect(&path).with_context(|| {
+            format!(
+                "failed to connect to {:?}, is synf dev running there?",
+                path
+            )
+        })?;
+        writeln!(stream, "{}", command(reload))?;
+
+        let mut reply = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut reply)
+            .context("failed to read reply from synf dev")?;
+        match reply.trim().strip_prefix("error: ") {
+            Some(error) => Err(eyre::eyre!("{}", error)),
+            None if reply.trim() == "ok" => Ok(()),
+            None => Err(eyre::eyre!(
+                "unexpected reply from synf dev: {:?}",
+                reply.trim()
+            )),
+        }
+    }
+}
+
+/// Starts accepting reload requests from `synf reload` in project directory.
+pub(crate) fn listen(project: &Path, runner: Arc<Mutex<Runner>>) -> eyre::Result<()> {
+    #[cfg(unix)]
+    return unix::listen(project, runner);
+    #[cfg(not(unix))]
+    {
+        let _ = (project, runner);
+        eprintln!("Control socket is not supported on this platform, synf reload would not work");
+        Ok(())
+    }
+}
+
+/// Asks `synf dev` running in project directory to reload and waits until it is done.
+pub(crate) fn send(project: &Path, reload: Reload) -> eyre::Result<()> {
+    #[cfg(unix)]
+    return unix::send(project, reload);
+    #[cfg(not(unix))]
+    {
+        let _ = (project, reload);
+        Err(eyre::eyre!("synf reload is not supported on this platform"))
+    }
+}
+
+/// Removes control socket, so that it would not be left behind after exit.
+pub(crate) fn cleanup(project: &Path) {
+    let path = socket_path(project);
+    if path.exists() {
+        let _ = std::fs::remove_file(path);
+    }
+}
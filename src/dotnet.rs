@@ -0,0 +1,248 @@
+use std::path::{Path, PathBuf};
+
+use eyre::Context;
+
+/// Files in project folder with given extension, sorted by name.
+fn files_with_extension(project: &Path, extension: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(project) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    files
+}
+
+/// C# projects relative to project folder, which are the ones in it
+/// or the ones listed by solutions in it.
+fn projects(project: &Path) -> Vec<PathBuf> {
+    let own = files_with_extension(project, "csproj");
+    if !own.is_empty() {
+        return own
+            .iter()
+            .filter_map(|path| path.strip_prefix(project).ok())
+            .map(Path::to_path_buf)
+            .collect();
+    }
+
+    let mut projects = Vec::new();
+    for solution in files_with_extension(project, "sln") {
+        let Ok(content) = std::fs::read_to_string(&solution) else {
+            continue;
+        };
+        // Project("{type}") = "Name", "path\Name.csproj", "{id}"
+        for line in content.lines().filter(|line| line.starts_with("Project(")) {
+            let Some(path) = line.split('"').nth(5) else {
+                continue;
+            };
+            let path = PathBuf::from(path.replace('\\', "/"));
+            if path.extension().is_some_and(|ext| ext == "csproj") && !projects.contains(&path) {
+                projects.push(path);
+            }
+        }
+    }
+    projects
+}
+
+/// Text of the first element with given name, f.e of property in csproj.
+fn property(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(xml[start..end].trim().to_string())
+}
+
+fn is_executable(csproj: &str) -> bool {
+    let output_type = property(csproj, "OutputType").unwrap_or_default();
+    // web projects are executables without saying so
+    output_type.eq_ignore_ascii_case("exe")
+        || output_type.eq_ignore_ascii_case("winexe")
+        || csproj.contains("Sdk=\"Microsoft.NET.Sdk.Web\"")
+}
+
+/// Finds dll that `dotnet build` makes, which is the output of the only project,
+/// or of the only executable project among several, relative to project folder.
+pub(crate) fn dll(project: &Path) -> eyre::Result<PathBuf> {
+    let mut candidates = Vec::new();
+    for path in projects(project) {
+        let file = project.join(&path);
+        let csproj =
+            std::fs::read_to_string(&file).with_context(|| format!("failed to read {:?}", file))?;
+        candidates.push((path, csproj));
+    }
+    if candidates.len() > 1 {
+        candidates.retain(|(_, csproj)| is_executable(csproj));
+    }
+
+    let (path, csproj) = match candidates.as_slice() {
+        [candidate] => candidate,
+        [] => {
+            return Err(eyre::eyre!(
+                "no executable C# projects found in {:?}",
+                project
+            ))
+        }
+        _ => {
+            let names: Vec<String> = candidates
+                .iter()
+                .map(|(path, _)| path.to_string_lossy().into_owned())
+                .collect();
+            return Err(eyre::eyre!(
+                "several executable C# projects found in {:?}: {}, set command in [run] to choose one",
+                project,
+                names.join(", ")
+            ));
+        }
+    };
+
+    let framework = property(csproj, "TargetFramework")
+        .or_else(|| {
+            // multi-targeting project is run with its first framework
+            property(csproj, "TargetFrameworks")
+                .and_then(|frameworks| Some(frameworks.split(';').next()?.trim().to_string()))
+        })
+        .ok_or_else(|| eyre::eyre!("target framework is missing in {:?}", path))?;
+    let name = match property(csproj, "AssemblyName") {
+        Some(name) => name,
+        None => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(dir
+        .join("bin")
+        .join("Debug")
+        .join(framework)
+        .join(format!("{}.dll", name)))
+}
+
+/// Paths relative to project that have sources of its C# projects and solutions.
+pub(crate) fn watch_paths(project: &Path) -> Vec<String> {
+    let mut paths = Vec::new();
+    for path in projects(project) {
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_string_lossy().into_owned(),
+            _ => ".".to_string(),
+        };
+        if !paths.contains(&dir) {
+            paths.push(dir);
+        }
+    }
+    for solution in files_with_extension(project, "sln") {
+        if let Some(name) = solution.file_name() {
+            paths.push(name.to_string_lossy().into_owned());
+        }
+    }
+    paths
+}
+
+/// Whether folder has C# project or solution.
+pub(crate) fn is_dotnet_project(folder: &Path) -> bool {
+    !files_with_extension(folder, "csproj").is_empty()
+        || !files_with_extension(folder, "sln").is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty folder for test project, removed when dropped.
+    struct Folder(PathBuf);
+
+    impl Folder {
+        fn new(name: &str) -> Folder {
+            let path =
+                std::env::temp_dir().join(format!("synf-dotnet-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Folder(path)
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+    }
+
+    impl Drop for Folder {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const SOLUTION: &str = r#"
+Microsoft Visual Studio Solution File, Format Version 12.00
+Project("{FAE04EC0-301F-11D3-BF4B-00C04F79EFBC}") = "Server", "src\Server\Server.csproj", "{11111111-1111-1111-1111-111111111111}"
+EndProject
+Project("{FAE04EC0-301F-11D3-BF4B-00C04F79EFBC}") = "Lib", "src\Lib\Lib.csproj", "{22222222-2222-2222-2222-222222222222}"
+EndProject
+Project("{2150E333-8FDC-42A3-9474-1A3956D46DE8}") = "Solution Items", "Solution Items", "{33333333-3333-3333-3333-333333333333}"
+EndProject
+Global
+EndGlobal
+"#;
+
+    #[test]
+    fn finds_projects_of_solution() {
+        let folder = Folder::new("solution");
+        folder.write("App.sln", SOLUTION);
+        assert_eq!(
+            projects(&folder.0),
+            vec![
+                PathBuf::from("src/Server/Server.csproj"),
+                PathBuf::from("src/Lib/Lib.csproj"),
+            ]
+        );
+        assert_eq!(
+            watch_paths(&folder.0),
+            vec![
+                "src/Server".to_string(),
+                "src/Lib".to_string(),
+                "App.sln".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_projects_in_folder_to_solution() {
+        let folder = Folder::new("own");
+        folder.write("App.sln", SOLUTION);
+        folder.write(
+            "App.csproj",
+            "<Project Sdk=\"Microsoft.NET.Sdk\"></Project>",
+        );
+        assert_eq!(projects(&folder.0), vec![PathBuf::from("App.csproj")]);
+    }
+
+    #[test]
+    fn finds_dll_of_executable_project() {
+        let folder = Folder::new("dll");
+        folder.write("App.sln", SOLUTION);
+        folder.write(
+            "src/Server/Server.csproj",
+            r#"<Project Sdk="Microsoft.NET.Sdk">
+  <PropertyGroup>
+    <OutputType>Exe</OutputType>
+    <TargetFrameworks>net8.0;net9.0</TargetFrameworks>
+    <AssemblyName>McpServer</AssemblyName>
+  </PropertyGroup>
+</Project>"#,
+        );
+        folder.write(
+            "src/Lib/Lib.csproj",
+            r#"<Project Sdk="Microsoft.NET.Sdk">
+  <PropertyGroup>
+    <TargetFramework>net8.0</TargetFramework>
+  </PropertyGroup>
+</Project>"#,
+        );
+        assert_eq!(
+            dll(&folder.0).unwrap(),
+            PathBuf::from("src/Server/bin/Debug/net8.0/McpServer.dll")
+        );
+    }
+}
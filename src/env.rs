@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use eyre::Context;
+
+use crate::config::EnvConfig;
+
+/// Resolves variables to set for command from given configs, where
+/// later configs override earlier ones and env tables override env files.
+pub(crate) fn resolve(
+    project: &Path,
+    configs: &[&EnvConfig],
+) -> eyre::Result<Vec<(String, String)>> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    let mut set = |name: String, value: String| {
+        vars.retain(|(existing, _)| *existing != name);
+        vars.push((name, value));
+    };
+    for config in configs {
+        if let Some(env_file) = &config.env_file {
+            let path = project.join(env_file);
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read env file {:?}", path))?;
+            for (name, value) in
+                parse(&content).with_context(|| format!("invalid env file {:?}", path))?
+            {
+                set(name, value);
+            }
+        }
+        for (name, value) in config.env.iter().flatten() {
+            set(name.clone(), interpolate(value));
+        }
+    }
+    Ok(vars)
+}
+
+/// Parses dotenv file with `NAME=value` lines, where value can be quoted
+/// with single quotes to be taken literally.
+fn parse(content: &str) -> eyre::Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(eyre::eyre!("line {} is not NAME=value", number + 1));
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(eyre::eyre!(
+                "line {} has invalid name {:?}",
+                number + 1,
+                name
+            ));
+        }
+        let value = parse_value(value.trim_start())
+            .with_context(|| format!("line {} has invalid value", number + 1))?;
+        vars.push((name.to_string(), value));
+    }
+    Ok(vars)
+}
+
+fn parse_value(value: &str) -> eyre::Result<String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        let Some((literal, _)) = quoted.split_once('\'') else {
+            return Err(eyre::eyre!("closing quote is missing"));
+        };
+        return Ok(literal.to_string());
+    }
+
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut unescaped = String::new();
+        let mut chars = quoted.chars();
+        loop {
+            match chars.next() {
+                None => return Err(eyre::eyre!("closing quote is missing")),
+                Some('"') => return Ok(interpolate(&unescaped)),
+                Some('\\') => match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    Some('t') => unescaped.push('\t'),
+                    Some(other) => unescaped.push(other),
+                    None => return Err(eyre::eyre!("closing quote is missing")),
+                },
+                Some(other) => unescaped.push(other),
+            }
+        }
+    }
+
+    // unquoted value can be followed by a comment
+    let value = match value.find(" #") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+    Ok(interpolate(value.trim_end()))
+}
+
+/// Replaces `${VAR}` with value of variable from environment of synf,
+/// which is empty for variables that are not set.
+fn interpolate(value: &str) -> String {
+    let mut interpolated = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        interpolated.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + end];
+        match std::env::var(name) {
+            Ok(var) => interpolated.push_str(&var),
+            Err(_) => eprintln!("Warning: variable {} used in env is not set", name),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    interpolated.push_str(rest);
+    interpolated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(content: &str) -> Vec<(String, String)> {
+        parse(content).unwrap()
+    }
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parses_plain_values() {
+        assert_eq!(
+            vars("A=1\n\n# comment\nexport B = two words \n"),
+            vec![var("A", "1"), var("B", "two words")]
+        );
+    }
+
+    #[test]
+    fn strips_comments_after_unquoted_values() {
+        assert_eq!(
+            vars("A=value # comment\nB=a#b\nC=\n"),
+            vec![var("A", "value"), var("B", "a#b"), var("C", "")]
+        );
+    }
+
+    #[test]
+    fn keeps_single_quoted_values_literally() {
+        assert_eq!(
+            vars(r"A='x # not comment ${PATH} \n' # comment"),
+            vec![var("A", r"x # not comment ${PATH} \n")]
+        );
+    }
+
+    #[test]
+    fn unescapes_double_quoted_values() {
+        assert_eq!(
+            vars(r#"A="line\nnext \"quoted\" # not comment" # comment"#),
+            vec![var("A", "line\nnext \"quoted\" # not comment")]
+        );
+    }
+
+    #[test]
+    fn interpolates_unquoted_and_double_quoted_values() {
+        let path = std::env::var("PATH").unwrap_or_default();
+        assert_eq!(
+            vars("A=${PATH}:x\nB=\"[${PATH}]\"\nC=${SYNF_TEST_UNSET_VARIABLE}"),
+            vec![
+                var("A", &format!("{}:x", path)),
+                var("B", &format!("[{}]", path)),
+                var("C", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        for content in ["NO_VALUE", "=1", "TWO WORDS=1", "A='open", "A=\"open"] {
+            assert!(parse(content).is_err(), "{:?} should be invalid", content);
+        }
+    }
+}
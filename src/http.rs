@@ -0,0 +1,596 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use eyre::Context;
+
+/// Largest request body that synf accepts, as it is kept in memory.
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Longest request line or header line, including line ending.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+const MAX_HEADERS: usize = 100;
+
+/// Request that exceeds limits of synf, to be answered with the status.
+#[derive(Debug)]
+pub(crate) struct TooLarge {
+    pub(crate) status: u16,
+    pub(crate) message: &'static str,
+}
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+const BODY_TOO_LARGE: TooLarge = TooLarge {
+    status: 413,
+    message: "Request body is too large",
+};
+
+const HEADERS_TOO_LARGE: TooLarge = TooLarge {
+    status: 431,
+    message: "Request headers are too large",
+};
+
+/// Reads line up to MAX_LINE_LENGTH, returns number of bytes read, which is 0 at the end of stream.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> eyre::Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_LENGTH as u64)
+        .read_line(line)?;
+    if read == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(HEADERS_TOO_LARGE.into());
+    }
+    Ok(read)
+}
+
+/// Minimal HTTP/1.1 request, enough to serve MCP Streamable HTTP transport.
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    /// Header names are lowercased.
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.as_str())
+    }
+}
+
+/// Reads header lines until empty line, lowercasing names.
+pub(crate) fn read_headers(reader: &mut impl BufRead) -> eyre::Result<HashMap<String, String>> {
+    let mut headers = HashMap::new();
+    for _ in 0..=MAX_HEADERS {
+        let mut line = String::new();
+        if read_line(reader, &mut line).context("failed to read header")? == 0 {
+            return Err(eyre::eyre!("connection closed while reading headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Err(HEADERS_TOO_LARGE.into())
+}
+
+/// Reads message body according to Content-Length or chunked Transfer-Encoding,
+/// up to MAX_BODY_SIZE.
+pub(crate) fn read_body(
+    reader: &mut impl BufRead,
+    headers: &HashMap<String, String>,
+) -> eyre::Result<Vec<u8>> {
+    let chunked = headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    if chunked {
+        let mut body = Vec::new();
+        ChunkedReader::new(reader)
+            .take(MAX_BODY_SIZE as u64 + 1)
+            .read_to_end(&mut body)
+            .context("failed to read chunked body")?;
+        if body.len() > MAX_BODY_SIZE {
+            return Err(BODY_TOO_LARGE.into());
+        }
+        return Ok(body);
+    }
+
+    let length = match headers.get("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .with_context(|| format!("invalid content length {:?}", length))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(BODY_TOO_LARGE.into());
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .context("failed to read body")?;
+    Ok(body)
+}
+
+pub(crate) fn read_request(reader: &mut impl BufRead) -> eyre::Result<Request> {
+    let mut request_line = String::new();
+    if read_line(reader, &mut request_line).context("failed to read request line")? == 0 {
+        return Err(eyre::eyre!("connection closed before request"));
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(eyre::eyre!("malformed request line {:?}", request_line));
+    };
+    let path = target.split('?').next().unwrap_or(target).to_string();
+    let method = method.to_string();
+
+    let headers = read_headers(reader)?;
+    let body = read_body(reader, &headers)?;
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
+
+/// Writes complete response and marks connection to be closed after it.
+pub(crate) fn write_response(
+    writer: &mut impl Write,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+/// Writes headers of Server-Sent Events stream, which lasts until connection is closed.
+pub(crate) fn write_event_stream_head(
+    writer: &mut impl Write,
+    headers: &[(&str, &str)],
+) -> std::io::Result<()> {
+    let mut head = String::from("HTTP/1.1 200 OK\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(
+        "Content-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    );
+    writer.write_all(head.as_bytes())?;
+    writer.flush()
+}
+
+/// Writes single Server-Sent Event carrying JSON-RPC message.
+pub(crate) fn write_event(writer: &mut impl Write, data: &str) -> std::io::Result<()> {
+    let mut event = String::from("event: message\n");
+    for line in data.trim_end().lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    writer.write_all(event.as_bytes())?;
+    writer.flush()
+}
+
+/// Plain `http://` URL, as synf only talks to local servers.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Url {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// Path with query, always starting with slash.
+    pub(crate) path: String,
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> eyre::Result<Url> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(eyre::eyre!(
+                "unsupported URL {:?}, only http:// URLs are supported",
+                url
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>()
+                    .with_context(|| format!("invalid port in URL {:?}", url))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(eyre::eyre!("missing host in URL {:?}", url));
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Resolves reference relative to this URL, as in SSE endpoint event.
+    pub(crate) fn join(&self, reference: &str) -> eyre::Result<Url> {
+        if reference.starts_with("http://") {
+            return Url::parse(reference);
+        }
+        let path = if reference.starts_with('/') {
+            reference.to_string()
+        } else {
+            let base = self.path.split('?').next().unwrap_or("/");
+            let dir = &base[..base.rfind('/').map(|index| index + 1).unwrap_or(0)];
+            format!("{}{}", dir, reference)
+        };
+        Ok(Url {
+            host: self.host.clone(),
+            port: self.port,
+            path,
+        })
+    }
+
+    pub(crate) fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Checks if something accepts connections at host and port of this URL.
+    pub(crate) fn accepts_connections(&self, timeout: Duration) -> bool {
+        let Ok(addrs) = self.address().to_socket_addrs() else {
+            return false;
+        };
+        addrs
+            .into_iter()
+            .any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok())
+    }
+}
+
+/// Response to request sent by synf, with body read lazily.
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    /// Header names are lowercased.
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: Box<dyn BufRead + Send>,
+}
+
+impl Response {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.as_str())
+    }
+
+    pub(crate) fn is_event_stream(&self) -> bool {
+        self.header("content-type")
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"))
+    }
+
+    pub(crate) fn text(mut self) -> eyre::Result<String> {
+        let mut body = String::new();
+        self.body
+            .read_to_string(&mut body)
+            .context("failed to read response body")?;
+        Ok(body)
+    }
+}
+
+/// Request written to server, which response can be read later.
+pub(crate) struct PendingResponse {
+    stream: TcpStream,
+}
+
+impl PendingResponse {
+    pub(crate) fn read(self) -> eyre::Result<Response> {
+        let mut reader = BufReader::new(self.stream);
+        let mut status_line = String::new();
+        if reader
+            .read_line(&mut status_line)
+            .context("failed to read response status")?
+            == 0
+        {
+            return Err(eyre::eyre!("connection closed before response"));
+        }
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| eyre::eyre!("malformed status line {:?}", status_line))?;
+        let headers = read_headers(&mut reader)?;
+
+        let chunked = headers
+            .get("transfer-encoding")
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+        let body: Box<dyn BufRead + Send> = if chunked {
+            Box::new(BufReader::new(ChunkedReader::new(reader)))
+        } else if let Some(length) = headers.get("content-length") {
+            let length = length
+                .parse::<u64>()
+                .with_context(|| format!("invalid content length {:?}", length))?;
+            Box::new(reader.take(length))
+        } else {
+            // body lasts until connection is closed
+            Box::new(reader)
+        };
+
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Writes request and leaves its response to be read later,
+/// so that server receives requests in the order they were sent.
+pub(crate) fn send(
+    url: &Url,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> eyre::Result<PendingResponse> {
+    let mut stream = TcpStream::connect(url.address())
+        .with_context(|| format!("failed to connect to {}", url.address()))?;
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        method,
+        url.path,
+        url.address(),
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .and_then(|_| stream.flush())
+        .with_context(|| format!("failed to send request to {}", url.address()))?;
+    Ok(PendingResponse { stream })
+}
+
+/// Server-Sent Event, with data lines joined.
+pub(crate) struct Event {
+    pub(crate) event: String,
+    pub(crate) data: String,
+}
+
+/// Reads next event from the stream, returns None once stream ends.
+pub(crate) fn read_event(reader: &mut impl BufRead) -> std::io::Result<Option<Event>> {
+    let mut event = String::new();
+    let mut data: Vec<String> = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            if data.is_empty() {
+                continue;
+            }
+            return Ok(Some(Event {
+                event: if event.is_empty() {
+                    "message".to_string()
+                } else {
+                    event
+                },
+                data: data.join("\n"),
+            }));
+        }
+        if line.starts_with(':') {
+            // comment, used by servers to keep connection alive
+            continue;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event = value.to_string(),
+            "data" => data.push(value.to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// Decodes body sent with chunked Transfer-Encoding.
+pub(crate) struct ChunkedReader<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut size_line = String::new();
+            if self.inner.read_line(&mut size_line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let size = size_line.trim().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size, 16).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid chunk size {:?}", size_line),
+                )
+            })?;
+            if size == 0 {
+                // skip trailers until empty line
+                loop {
+                    let mut line = String::new();
+                    if self.inner.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                }
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = size;
+        }
+
+        let max = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read;
+        if self.remaining == 0 {
+            // consume CRLF following chunk data
+            let mut crlf = String::new();
+            self.inner.read_line(&mut crlf)?;
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(path: &str) -> Url {
+        Url {
+            host: "localhost".to_string(),
+            port: 8080,
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn decodes_chunked_body_with_trailers() {
+        let mut stream =
+            "4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\nX-Trailer: 1\r\n\r\nNEXT"
+                .as_bytes();
+        let mut body = String::new();
+        ChunkedReader::new(&mut stream)
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "Wikipedia");
+        // rest of the connection is left unread
+        assert_eq!(stream, b"NEXT");
+    }
+
+    #[test]
+    fn rejects_truncated_and_invalid_chunks() {
+        let mut body = Vec::new();
+        let error = ChunkedReader::new("5\r\nab".as_bytes())
+            .read_to_end(&mut body)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        let error = ChunkedReader::new("zz\r\nab\r\n".as_bytes())
+            .read_to_end(&mut body)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_chunked_request() {
+        let mut stream = "POST /mcp?x=1 HTTP/1.1\r\nTransfer-Encoding: chunked\r\nMcp-Session-Id: s\r\n\r\n2\r\n{}\r\n0\r\n\r\n".as_bytes();
+        let request = read_request(&mut stream).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/mcp");
+        assert_eq!(request.header("mcp-session-id"), Some("s"));
+        assert_eq!(request.body, b"{}");
+    }
+
+    #[test]
+    fn rejects_too_large_body() {
+        let headers = HashMap::from([(
+            "content-length".to_string(),
+            (MAX_BODY_SIZE + 1).to_string(),
+        )]);
+        let error = read_body(&mut "".as_bytes(), &headers).unwrap_err();
+        assert_eq!(error.downcast_ref::<TooLarge>().unwrap().status, 413);
+    }
+
+    #[test]
+    fn joins_relative_endpoints() {
+        let base = url("/mcp/sse?token=1");
+        assert_eq!(
+            base.join("/messages?session=a").unwrap(),
+            url("/messages?session=a")
+        );
+        assert_eq!(
+            base.join("messages?session=a").unwrap(),
+            url("/mcp/messages?session=a")
+        );
+        assert_eq!(url("/sse").join("messages").unwrap(), url("/messages"));
+        assert_eq!(
+            base.join("http://127.0.0.1:9000/messages").unwrap(),
+            Url {
+                host: "127.0.0.1".to_string(),
+                port: 9000,
+                path: "/messages".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(Url::parse("http://localhost:8080").unwrap(), url("/"));
+        assert_eq!(Url::parse("http://localhost/mcp").unwrap().port, 80);
+        assert!(Url::parse("https://localhost/mcp").is_err());
+        assert!(Url::parse("http://:80/mcp").is_err());
+    }
+
+    #[test]
+    fn reads_events() {
+        let mut stream = ": keep alive\r\n\r\nevent: endpoint\r\ndata: /messages\r\n\r\ndata: {\"a\":\ndata:1}\n\n"
+            .as_bytes();
+        let event = read_event(&mut stream).unwrap().unwrap();
+        assert_eq!(event.event, "endpoint");
+        assert_eq!(event.data, "/messages");
+        let event = read_event(&mut stream).unwrap().unwrap();
+        assert_eq!(event.event, "message");
+        assert_eq!(event.data, "{\"a\":\n1}");
+        assert!(read_event(&mut stream).unwrap().is_none());
+    }
+}
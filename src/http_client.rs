@@ -0,0 +1,253 @@
+use std::{
+    io::BufRead,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded, unbounded, Sender};
+use eyre::Context;
+use serde_json::Value;
+
+use crate::{
+    http::{self, Response, Url},
+    jsonrpc::{self, Frame, Message},
+    transport::{Connection, ServerInput},
+};
+
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+const ENDPOINT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connects to server exposing Streamable HTTP transport at given URL.
+pub(crate) fn streamable(url: Url) -> Connection {
+    let (sender, receiver) = unbounded::<String>();
+    Connection {
+        input: Box::new(Streamable {
+            url,
+            session: Arc::new(Mutex::new(None)),
+            output: sender,
+        }),
+        output: receiver,
+        broken: crossbeam_channel::never(),
+    }
+}
+
+struct Streamable {
+    url: Url,
+    session: Arc<Mutex<Option<String>>>,
+    output: Sender<String>,
+}
+
+impl ServerInput for Streamable {
+    fn send(&mut self, message: &str) -> eyre::Result<()> {
+        let session = self.session.lock().unwrap().clone();
+        let mut headers = vec![
+            ("Content-Type", "application/json"),
+            ("Accept", "application/json, text/event-stream"),
+        ];
+        if let Some(session) = &session {
+            headers.push((SESSION_HEADER, session));
+        }
+        let pending = http::send(&self.url, "POST", &headers, message.as_bytes())?;
+
+        let url = self.url.clone();
+        let session = self.session.clone();
+        let output = self.output.clone();
+        let request_id = request_id(message);
+        // response is read separately, so that long running requests
+        // do not block other messages sent to server in the meantime
+        thread::spawn(move || {
+            let result = pending.read().and_then(|response| {
+                if response.status >= 300 {
+                    let status = response.status;
+                    let body = response.text().unwrap_or_default();
+                    return Err(eyre::eyre!("server responded with {}: {}", status, body));
+                }
+
+                if let Some(id) = response.header(&SESSION_HEADER.to_ascii_lowercase()) {
+                    let mut session = session.lock().unwrap();
+                    if session.as_deref() != Some(id) {
+                        *session = Some(id.to_string());
+                        open_stream(url, id.to_string(), output.clone());
+                    }
+                }
+
+                read_messages(response, &output)
+            });
+
+            if let Err(e) = result {
+                eprintln!("Failed to send message to server: {:#}", e);
+                if let Some(id) = request_id {
+                    // answer request instead of server, so that client would not wait forever
+                    let error =
+                        jsonrpc::error_response(&id, jsonrpc::INTERNAL_ERROR, &format!("{:#}", e));
+                    let _ = output.send(error);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Drop for Streamable {
+    fn drop(&mut self) {
+        // let server know that session is over, if it is still listening
+        if let Some(session) = self.session.lock().unwrap().take() {
+            if let Ok(pending) = http::send(&self.url, "DELETE", &[(SESSION_HEADER, &session)], b"")
+            {
+                let _ = pending.read();
+            }
+        }
+    }
+}
+
+/// Opens GET stream for messages that server sends on its own, if it supports them.
+fn open_stream(url: Url, session: String, output: Sender<String>) {
+    thread::spawn(move || {
+        let response = http::send(
+            &url,
+            "GET",
+            &[("Accept", "text/event-stream"), (SESSION_HEADER, &session)],
+            b"",
+        )
+        .and_then(|pending| pending.read());
+        match response {
+            Ok(response) if response.status == 200 && response.is_event_stream() => {
+                if let Err(e) = read_messages(response, &output) {
+                    eprintln!("Server stream ended with error: {:#}", e);
+                }
+            }
+            Ok(response) => {
+                // server does not support it, which is allowed by transport
+                eprintln!(
+                    "Server did not open stream for its messages, status {}",
+                    response.status
+                );
+            }
+            Err(e) => {
+                eprintln!("Failed to open stream for server messages: {:#}", e);
+            }
+        }
+    });
+}
+
+/// Sends messages from JSON or event stream response body to output.
+fn read_messages(response: Response, output: &Sender<String>) -> eyre::Result<()> {
+    if response.status == 202 {
+        return Ok(());
+    }
+
+    if response.is_event_stream() {
+        let mut body = response.body;
+        while let Some(event) = http::read_event(&mut body).context("failed to read event")? {
+            if event.event == "message" && output.send(event.data).is_err() {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let body = response.text()?;
+    if body.trim().is_empty() {
+        return Ok(());
+    }
+    match serde_json::from_str::<Value>(&body).context("server responded with invalid JSON")? {
+        Value::Array(messages) => {
+            for message in messages {
+                let _ = output.send(message.to_string());
+            }
+        }
+        message => {
+            let _ = output.send(message.to_string());
+        }
+    }
+    Ok(())
+}
+
+fn request_id(message: &str) -> Option<Value> {
+    match jsonrpc::parse(message).ok()? {
+        Frame::Single(Message::Request { id, .. }) => Some(id),
+        _ => None,
+    }
+}
+
+/// Connects to server exposing legacy HTTP with SSE transport,
+/// where URL points to the event stream announcing endpoint for messages.
+pub(crate) fn sse(url: Url) -> eyre::Result<Connection> {
+    let response = http::send(&url, "GET", &[("Accept", "text/event-stream")], b"")?
+        .read()
+        .context("failed to open event stream")?;
+    if response.status != 200 {
+        return Err(eyre::eyre!(
+            "server responded with {} to event stream request",
+            response.status
+        ));
+    }
+
+    let (sender, receiver) = unbounded::<String>();
+    let (endpoint_sender, endpoint_receiver) = bounded::<String>(1);
+    let mut body = response.body;
+    thread::spawn(move || {
+        loop {
+            match http::read_event(&mut body) {
+                Ok(Some(event)) => match event.event.as_str() {
+                    "endpoint" => {
+                        let _ = endpoint_sender.send(event.data);
+                    }
+                    "message" => {
+                        if sender.send(event.data).is_err() {
+                            break;
+                        }
+                    }
+                    _ => {}
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Server event stream ended with error: {:?}", e);
+                    break;
+                }
+            }
+        }
+        eprintln!("exit ended event stream processing");
+    });
+
+    let endpoint = endpoint_receiver
+        .recv_timeout(ENDPOINT_TIMEOUT)
+        .context("server did not announce endpoint for messages")?;
+    let endpoint = url.join(&endpoint)?;
+    eprintln!("Server announced endpoint {}", endpoint.path);
+
+    Ok(Connection {
+        input: Box::new(Sse { endpoint }),
+        output: receiver,
+        broken: crossbeam_channel::never(),
+    })
+}
+
+struct Sse {
+    endpoint: Url,
+}
+
+impl ServerInput for Sse {
+    fn send(&mut self, message: &str) -> eyre::Result<()> {
+        let pending = http::send(
+            &self.endpoint,
+            "POST",
+            &[("Content-Type", "application/json")],
+            message.as_bytes(),
+        )?;
+        // responses come through event stream, only errors are interesting here
+        thread::spawn(move || match pending.read() {
+            Ok(response) if response.status >= 300 => {
+                let status = response.status;
+                let mut body = response.body;
+                let mut text = String::new();
+                let _ = body.read_line(&mut text);
+                eprintln!("Server responded with {} to message: {}", status, text);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to send message to server: {:#}", e),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,682 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::BuildHasher,
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{unbounded, RecvTimeoutError, Sender};
+use eyre::Context;
+use serde_json::{json, Value};
+
+use crate::{
+    client::{Client, ClientSink},
+    http::{self, Request},
+};
+
+const ENDPOINT: &str = "/mcp";
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Exposes proxied server to clients using Streamable HTTP transport.
+///
+/// Every HTTP session is multiplexed onto the single server process,
+/// so ids of client requests are prefixed with session id before
+/// forwarding them and restored when routing responses back.
+pub(crate) fn listen(addr: &str) -> eyre::Result<Client> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to listen on {}", addr))?;
+    let local_addr = listener.local_addr()?;
+    eprintln!(
+        "Listening for Streamable HTTP clients on http://{}{}",
+        local_addr, ENDPOINT
+    );
+
+    let (sender, receiver) = unbounded::<String>();
+    let frontend = Arc::new(Frontend::new(sender));
+
+    let accepting = frontend.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let frontend = accepting.clone();
+                    thread::spawn(move || frontend.handle_connection(stream));
+                }
+                Err(e) => {
+                    eprintln!("Failed to accept HTTP connection: {:?}", e);
+                }
+            }
+        }
+    });
+
+    Ok(Client {
+        messages: receiver,
+        sink: frontend,
+        // sessions come and go, synf keeps running until Ctrl-C
+        disconnected: crossbeam_channel::never(),
+    })
+}
+
+#[derive(Default)]
+struct Initialize {
+    /// Result of the first initialize request, repeated to later sessions.
+    result: Option<Value>,
+    in_flight: bool,
+    /// Whether initialized notification was already forwarded to server.
+    notified: bool,
+}
+
+struct Session {
+    id: String,
+    /// Senders waiting for responses by prefixed request id.
+    pending: Mutex<HashMap<String, Sender<Value>>>,
+    /// Open GET streams for messages initiated by server.
+    streams: Mutex<Vec<Sender<String>>>,
+    subscriptions: Mutex<HashSet<String>>,
+}
+
+impl Session {
+    /// Sends message to every open stream of the session.
+    fn push(&self, message: &str) {
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|stream| stream.send(message.to_string()).is_ok());
+    }
+
+    /// Sends message to a single open stream, returns false if there is none.
+    fn push_one(&self, message: &str) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        while let Some(stream) = streams.first() {
+            if stream.send(message.to_string()).is_ok() {
+                return true;
+            }
+            streams.remove(0);
+        }
+        false
+    }
+}
+
+struct Frontend {
+    to_server: Sender<String>,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    initialize: Mutex<Initialize>,
+    initialize_done: Condvar,
+    session_counter: AtomicU64,
+    random: RandomState,
+    /// Number of sessions subscribed to resource by its URI, as server
+    /// is subscribed once for all of them.
+    subscribers: Mutex<HashMap<String, usize>>,
+}
+
+impl Frontend {
+    fn new(to_server: Sender<String>) -> Frontend {
+        Frontend {
+            to_server,
+            sessions: Mutex::new(HashMap::new()),
+            initialize: Mutex::new(Initialize::default()),
+            initialize_done: Condvar::new(),
+            session_counter: AtomicU64::new(0),
+            random: RandomState::new(),
+            subscribers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn handle_connection(&self, stream: TcpStream) {
+        let mut reader = BufReader::new(match stream.try_clone() {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to clone HTTP connection: {:?}", e);
+                return;
+            }
+        });
+        let mut stream = stream;
+
+        let request = match http::read_request(&mut reader) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Failed to read HTTP request: {:#}", e);
+                if let Some(too_large) = e.downcast_ref::<http::TooLarge>() {
+                    let _ = http::write_response(
+                        &mut stream,
+                        too_large.status,
+                        &[],
+                        too_large.message.as_bytes(),
+                    );
+                }
+                return;
+            }
+        };
+
+        if let Err(e) = self.handle_request(&request, &mut stream) {
+            eprintln!("Failed to handle HTTP request: {:?}", e);
+        }
+    }
+
+    fn handle_request(&self, request: &Request, stream: &mut TcpStream) -> std::io::Result<()> {
+        if request.path != ENDPOINT {
+            return http::write_response(stream, 404, &[], b"Not Found");
+        }
+
+        // protects local server from DNS rebinding attacks
+        if let Some(origin) = request.header("origin") {
+            if !is_local_origin(origin) {
+                return http::write_response(stream, 403, &[], b"Forbidden origin");
+            }
+        }
+
+        match request.method.as_str() {
+            "POST" => self.handle_post(request, stream),
+            "GET" => self.handle_get(request, stream),
+            "DELETE" => self.handle_delete(request, stream),
+            _ => http::write_response(stream, 405, &[("Allow", "GET, POST, DELETE")], b""),
+        }
+    }
+
+    fn session(&self, request: &Request) -> Result<Arc<Session>, (u16, &'static [u8])> {
+        let Some(id) = request.header(&SESSION_HEADER.to_ascii_lowercase()) else {
+            return Err((400, b"Missing session id"));
+        };
+        match self.sessions.lock().unwrap().get(id) {
+            Some(session) => Ok(session.clone()),
+            None => Err((404, b"Session not found")),
+        }
+    }
+
+    fn new_session(&self) -> Arc<Session> {
+        let counter = self.session_counter.fetch_add(1, Ordering::SeqCst);
+        let id = format!("{:016x}{:04x}", self.random.hash_one(counter), counter);
+        let session = Arc::new(Session {
+            id: id.clone(),
+            pending: Mutex::new(HashMap::new()),
+            streams: Mutex::new(Vec::new()),
+            subscriptions: Mutex::new(HashSet::new()),
+        });
+        self.sessions.lock().unwrap().insert(id, session.clone());
+        eprintln!("Started HTTP session {}", session.id);
+        session
+    }
+
+    fn handle_post(&self, request: &Request, stream: &mut TcpStream) -> std::io::Result<()> {
+        let body: Value = match serde_json::from_slice(&request.body) {
+            Ok(body) => body,
+            Err(e) => {
+                let error = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32700, "message": format!("Parse error: {}", e)},
+                });
+                return http::write_response(
+                    stream,
+                    400,
+                    &[("Content-Type", "application/json")],
+                    error.to_string().as_bytes(),
+                );
+            }
+        };
+
+        let (messages, batch) = match body {
+            Value::Array(messages) => (messages, true),
+            message => (vec![message], false),
+        };
+
+        let initializing = messages
+            .iter()
+            .any(|message| method(message) == Some("initialize"));
+        let session = if initializing {
+            self.new_session()
+        } else {
+            match self.session(request) {
+                Ok(session) => session,
+                Err((status, body)) => return http::write_response(stream, status, &[], body),
+            }
+        };
+
+        let mut responses = Vec::new();
+        for message in messages {
+            match method(&message) {
+                Some("initialize") if message.get("id").is_some() => {
+                    responses.push(Ok(self.initialize(&session, message)));
+                }
+                Some("notifications/initialized") => {
+                    let mut initialize = self.initialize.lock().unwrap();
+                    if !initialize.notified {
+                        initialize.notified = true;
+                        self.forward(&message);
+                    }
+                }
+                Some("notifications/cancelled") => self.cancel(&session, message),
+                Some(method) if message.get("id").is_some() => {
+                    if self.track_subscription(&session, method, &message) {
+                        responses.push(Err(self.request(&session, message)));
+                    } else {
+                        // other sessions still need updates of the resource from server
+                        responses.push(Ok(
+                            json!({"jsonrpc": "2.0", "id": message["id"], "result": {}}),
+                        ));
+                    }
+                }
+                _ => {
+                    // notifications and responses to server requests go as is
+                    self.forward(&message);
+                }
+            }
+        }
+
+        if responses.is_empty() {
+            return http::write_response(stream, 202, &[(SESSION_HEADER, &session.id)], b"");
+        }
+
+        let mut results = Vec::new();
+        for response in responses {
+            match response {
+                Ok(response) => results.push(response),
+                Err(waiting) => match waiting.recv() {
+                    Ok(response) => results.push(response),
+                    Err(_) => {
+                        // session was deleted while waiting
+                        return http::write_response(stream, 404, &[], b"Session not found");
+                    }
+                },
+            }
+        }
+
+        let body = if batch {
+            Value::Array(results)
+        } else {
+            results.remove(0)
+        };
+        http::write_response(
+            stream,
+            200,
+            &[
+                ("Content-Type", "application/json"),
+                (SESSION_HEADER, &session.id),
+            ],
+            body.to_string().as_bytes(),
+        )
+    }
+
+    /// Responds to initialize from cache or forwards it, if it is the first one.
+    fn initialize(&self, session: &Session, message: Value) -> Value {
+        let mut initialize = self.initialize.lock().unwrap();
+        loop {
+            if let Some(result) = &initialize.result {
+                return json!({"jsonrpc": "2.0", "id": message["id"], "result": result});
+            }
+            if !initialize.in_flight {
+                initialize.in_flight = true;
+                break;
+            }
+            initialize = self.initialize_done.wait(initialize).unwrap();
+        }
+        drop(initialize);
+
+        let response = self.request(session, message).recv().unwrap_or_else(|_| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {"code": -32603, "message": "Session closed during initialization"},
+            })
+        });
+
+        let mut initialize = self.initialize.lock().unwrap();
+        initialize.in_flight = false;
+        if let Some(result) = response.get("result") {
+            initialize.result = Some(result.clone());
+        }
+        self.initialize_done.notify_all();
+        response
+    }
+
+    /// Forwards request to server with prefixed id and returns receiver of its response.
+    fn request(&self, session: &Session, mut message: Value) -> crossbeam_channel::Receiver<Value> {
+        let id = prefixed_id(session, &message["id"]);
+        let (sender, receiver) = unbounded();
+        session.pending.lock().unwrap().insert(id.clone(), sender);
+        message["id"] = Value::String(id);
+        self.forward(&message);
+        receiver
+    }
+
+    /// Forwards cancellation of request, which server only knows by prefixed id.
+    fn cancel(&self, session: &Session, mut message: Value) {
+        if let Some(request_id) = message["params"].get("requestId") {
+            message["params"]["requestId"] = Value::String(prefixed_id(session, request_id));
+        }
+        self.forward(&message);
+    }
+
+    /// Tracks resource subscriptions of session, returns false for unsubscribe
+    /// that must not reach server, as other sessions are still subscribed.
+    fn track_subscription(&self, session: &Session, method: &str, message: &Value) -> bool {
+        let Some(uri) = message["params"]["uri"].as_str() else {
+            return true;
+        };
+        let mut subscriptions = session.subscriptions.lock().unwrap();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        match method {
+            "resources/subscribe" => {
+                if subscriptions.insert(uri.to_string()) {
+                    *subscribers.entry(uri.to_string()).or_default() += 1;
+                }
+                true
+            }
+            // unsubscribe from resource that session has not subscribed to is for server to answer
+            "resources/unsubscribe" if subscriptions.remove(uri) => {
+                drop_subscriber(&mut subscribers, uri)
+            }
+            _ => true,
+        }
+    }
+
+    fn forward(&self, message: &Value) {
+        if let Err(e) = self.to_server.send(message.to_string()) {
+            eprintln!("Failed to forward message to server: {}", e);
+        }
+    }
+
+    fn handle_get(&self, request: &Request, stream: &mut TcpStream) -> std::io::Result<()> {
+        let accepts_events = request
+            .header("accept")
+            .is_some_and(|accept| accept.contains("text/event-stream"));
+        if !accepts_events {
+            return http::write_response(stream, 406, &[], b"Expected text/event-stream");
+        }
+
+        let session = match self.session(request) {
+            Ok(session) => session,
+            Err((status, body)) => return http::write_response(stream, status, &[], body),
+        };
+
+        let (sender, receiver) = unbounded::<String>();
+        session.streams.lock().unwrap().push(sender);
+        // release session, so that deleting it would close the stream
+        let session_id = session.id.clone();
+        drop(session);
+
+        http::write_event_stream_head(stream, &[(SESSION_HEADER, &session_id)])?;
+        loop {
+            match receiver.recv_timeout(KEEP_ALIVE_INTERVAL) {
+                Ok(message) => http::write_event(stream, &message)?,
+                Err(RecvTimeoutError::Timeout) => {
+                    // comment line keeps connection alive and detects closed ones
+                    std::io::Write::write_all(stream, b": ping\n\n")?;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    fn handle_delete(&self, request: &Request, stream: &mut TcpStream) -> std::io::Result<()> {
+        let session = match self.session(request) {
+            Ok(session) => session,
+            Err((status, body)) => return http::write_response(stream, status, &[], body),
+        };
+        self.sessions.lock().unwrap().remove(&session.id);
+        eprintln!("Closed HTTP session {}", session.id);
+
+        let uris: Vec<String> = session.subscriptions.lock().unwrap().drain().collect();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        for uri in uris {
+            if drop_subscriber(&mut subscribers, &uri) {
+                // response is dropped, as nobody waits for it
+                let id = json!(format!("unsubscribe {}", uri));
+                self.forward(&json!({
+                    "jsonrpc": "2.0",
+                    "id": prefixed_id(&session, &id),
+                    "method": "resources/unsubscribe",
+                    "params": {"uri": uri},
+                }));
+            }
+        }
+        http::write_response(stream, 200, &[], b"")
+    }
+
+    fn route(&self, message: Value) {
+        if let Some(method) = method(&message) {
+            let sessions: Vec<Arc<Session>> =
+                self.sessions.lock().unwrap().values().cloned().collect();
+            let serialized = message.to_string();
+
+            if message.get("id").is_some() {
+                // requests from server must be answered by exactly one client
+                if !sessions.iter().any(|session| session.push_one(&serialized)) {
+                    eprintln!(
+                        "Dropping {} request from server, no client has open stream",
+                        method
+                    );
+                }
+                return;
+            }
+
+            if method == "notifications/resources/updated" {
+                let uri = message["params"]["uri"].as_str().unwrap_or_default();
+                for session in sessions {
+                    if session.subscriptions.lock().unwrap().contains(uri) {
+                        session.push(&serialized);
+                    }
+                }
+                return;
+            }
+
+            for session in sessions {
+                session.push(&serialized);
+            }
+            return;
+        }
+
+        let Some(id) = message
+            .get("id")
+            .and_then(|id| id.as_str())
+            .map(String::from)
+        else {
+            eprintln!("Dropping unexpected message from server: {}", message);
+            return;
+        };
+        let Some((session_id, original_id)) = id.split_once(':') else {
+            eprintln!("Dropping response with unknown id {:?}", id);
+            return;
+        };
+        let session = self.sessions.lock().unwrap().get(session_id).cloned();
+        let waiting = session.and_then(|session| session.pending.lock().unwrap().remove(&id));
+        let Some(waiting) = waiting else {
+            eprintln!("Dropping response for closed request {:?}", id);
+            return;
+        };
+
+        let mut message = message;
+        message["id"] = serde_json::from_str(original_id).unwrap_or(Value::Null);
+        let _ = waiting.send(message);
+    }
+}
+
+impl ClientSink for Frontend {
+    fn send(&self, message: &str) {
+        match serde_json::from_str::<Value>(message) {
+            Ok(Value::Array(messages)) => messages.into_iter().for_each(|m| self.route(m)),
+            Ok(message) => self.route(message),
+            Err(e) => eprintln!("Dropping invalid message from server ({}): {}", e, message),
+        }
+    }
+}
+
+/// Id of request from session as server knows it, which is unique among sessions
+/// and has original id serialized as JSON to restore it, f.e `"abc:1"` or `"abc:\"1\""`.
+fn prefixed_id(session: &Session, id: &Value) -> String {
+    format!("{}:{}", session.id, id)
+}
+
+fn method(message: &Value) -> Option<&str> {
+    message.get("method").and_then(|method| method.as_str())
+}
+
+/// Drops one of sessions subscribed to resource, returns true if it was the last one.
+fn drop_subscriber(subscribers: &mut HashMap<String, usize>, uri: &str) -> bool {
+    match subscribers.get_mut(uri) {
+        Some(count) if *count > 1 => {
+            *count -= 1;
+            false
+        }
+        _ => {
+            subscribers.remove(uri);
+            true
+        }
+    }
+}
+
+fn is_local_origin(origin: &str) -> bool {
+    let host = origin
+        .split("://")
+        .nth(1)
+        .unwrap_or(origin)
+        .trim_end_matches('/');
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(ipv6),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::Receiver;
+
+    use super::*;
+
+    fn frontend() -> (Frontend, Receiver<String>) {
+        let (sender, receiver) = unbounded();
+        (Frontend::new(sender), receiver)
+    }
+
+    fn forwarded(to_server: &Receiver<String>) -> Value {
+        serde_json::from_str(&to_server.try_recv().unwrap()).unwrap()
+    }
+
+    /// Opens stream of session for messages initiated by server.
+    fn stream(session: &Session) -> Receiver<String> {
+        let (sender, receiver) = unbounded();
+        session.streams.lock().unwrap().push(sender);
+        receiver
+    }
+
+    #[test]
+    fn restores_ids_of_responses() {
+        let (frontend, to_server) = frontend();
+        let session = frontend.new_session();
+        for id in [json!(1), json!("1"), json!("a:b"), json!(null)] {
+            let response = frontend.request(
+                &session,
+                json!({"jsonrpc": "2.0", "id": id, "method": "tools/list"}),
+            );
+            let request = forwarded(&to_server);
+            let prefixed = request["id"].as_str().unwrap();
+            assert!(prefixed.starts_with(&format!("{}:", session.id)));
+
+            frontend.send(&json!({"jsonrpc": "2.0", "id": prefixed, "result": {}}).to_string());
+            assert_eq!(
+                response.try_recv().unwrap(),
+                json!({"jsonrpc": "2.0", "id": id, "result": {}})
+            );
+        }
+    }
+
+    #[test]
+    fn routes_responses_to_their_session() {
+        let (frontend, to_server) = frontend();
+        let first = frontend.new_session();
+        let second = frontend.new_session();
+        let first_response =
+            frontend.request(&first, json!({"jsonrpc": "2.0", "id": 1, "method": "ping"}));
+        let second_response = frontend.request(
+            &second,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "ping"}),
+        );
+        let first_id = forwarded(&to_server)["id"].clone();
+        let second_id = forwarded(&to_server)["id"].clone();
+        assert_ne!(first_id, second_id);
+
+        frontend.send(&json!({"jsonrpc": "2.0", "id": second_id, "result": "second"}).to_string());
+        assert_eq!(second_response.try_recv().unwrap()["result"], "second");
+        assert!(first_response.try_recv().is_err());
+    }
+
+    #[test]
+    fn prefixes_ids_of_cancelled_requests() {
+        let (frontend, to_server) = frontend();
+        let session = frontend.new_session();
+        let _response = frontend.request(
+            &session,
+            json!({"jsonrpc": "2.0", "id": "x", "method": "slow"}),
+        );
+        let request = forwarded(&to_server);
+        frontend.cancel(
+            &session,
+            json!({"jsonrpc": "2.0", "method": "notifications/cancelled", "params": {"requestId": "x"}}),
+        );
+        assert_eq!(forwarded(&to_server)["params"]["requestId"], request["id"]);
+    }
+
+    fn subscription(method: &str, uri: &str) -> Value {
+        json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": {"uri": uri}})
+    }
+
+    #[test]
+    fn sends_resource_updates_only_to_subscribed_sessions() {
+        let (frontend, _to_server) = frontend();
+        let subscribed = frontend.new_session();
+        let other = frontend.new_session();
+        let subscribed_stream = stream(&subscribed);
+        let other_stream = stream(&other);
+        let subscribe = subscription("resources/subscribe", "file:///a");
+        assert!(frontend.track_subscription(&subscribed, "resources/subscribe", &subscribe));
+
+        for uri in ["file:///a", "file:///b"] {
+            frontend.send(
+                &json!({"jsonrpc": "2.0", "method": "notifications/resources/updated", "params": {"uri": uri}})
+                    .to_string(),
+            );
+        }
+        let received: Vec<Value> = subscribed_stream
+            .try_iter()
+            .map(|message| serde_json::from_str(&message).unwrap())
+            .collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["params"]["uri"], "file:///a");
+        assert!(other_stream.try_recv().is_err());
+
+        // other notifications go to every session
+        frontend.send(r#"{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}"#);
+        assert!(subscribed_stream.try_recv().is_ok());
+        assert!(other_stream.try_recv().is_ok());
+    }
+
+    #[test]
+    fn unsubscribes_server_when_last_session_unsubscribes() {
+        let (frontend, _to_server) = frontend();
+        let first = frontend.new_session();
+        let second = frontend.new_session();
+        let subscribe = subscription("resources/subscribe", "file:///a");
+        let unsubscribe = subscription("resources/unsubscribe", "file:///a");
+        let track = |session: &Session, message: &Value| {
+            frontend.track_subscription(session, method(message).unwrap(), message)
+        };
+
+        assert!(track(&first, &subscribe));
+        assert!(track(&second, &subscribe));
+        // repeated subscription of the same session is not counted twice
+        assert!(track(&second, &subscribe));
+        assert!(!track(&first, &unsubscribe));
+        assert!(track(&second, &unsubscribe));
+        // session that is not subscribed gets answer from server
+        assert!(track(&first, &unsubscribe));
+        assert!(frontend.subscribers.lock().unwrap().is_empty());
+    }
+}
@@ -3,9 +3,31 @@ use std::{fs, path::Path};
 use eyre::Context;
 use inquire::Select;
 
-use crate::{config::{self, Language}, runner, utils};
+use crate::{config::{self, Language}, dotnet, runner, utils};
 
 fn detect_language(folder: &Path) -> config::Language {
+    // deno and bun projects can have package.json too
+    let has_deno_json = folder.join("deno.json").exists() || folder.join("deno.jsonc").exists();
+    if has_deno_json {
+        return config::Language::Deno;
+    }
+    let has_bun_lock = folder.join("bun.lockb").exists() || folder.join("bun.lock").exists();
+    if has_bun_lock {
+        return config::Language::Bun;
+    }
+    // projects in these languages often have package.json for frontend assets
+    let has_gemfile = folder.join("Gemfile").exists();
+    if has_gemfile {
+        return config::Language::Ruby;
+    }
+    let has_composer_json = folder.join("composer.json").exists();
+    if has_composer_json {
+        return config::Language::Php;
+    }
+    let has_mix_exs = folder.join("mix.exs").exists();
+    if has_mix_exs {
+        return config::Language::Elixir;
+    }
     let has_package_json = folder.join("package.json").exists();
     if has_package_json {
         return config::Language::Typescript;
@@ -14,19 +36,46 @@ fn detect_language(folder: &Path) -> config::Language {
     if has_pyproject_toml {
         return config::Language::Python;
     }
+    let has_pom_xml = folder.join("pom.xml").exists();
+    if has_pom_xml {
+        return config::Language::Java;
+    }
     let has_build_gradle = folder.join("build.gradle").exists();
     let has_build_gradle_kts = folder.join("build.gradle.kts").exists();
     if has_build_gradle || has_build_gradle_kts {
+        // gradle builds both, sources tell them apart
+        let has_java_sources = folder.join("src/main/java").exists();
+        let has_kotlin_sources = folder.join("src/main/kotlin").exists();
+        if has_java_sources && !has_kotlin_sources {
+            return config::Language::Java;
+        }
         return config::Language::Kotlin;
     }
     let has_go_mod = folder.join("go.mod").exists();
     if has_go_mod {
         return config::Language::Golang;
     }
+    // workspace root has it too, even if binary is in one of members
+    let has_cargo_toml = folder.join("Cargo.toml").exists();
+    if has_cargo_toml {
+        return config::Language::Rust;
+    }
+    let has_csproj_or_sln = dotnet::is_dotnet_project(folder);
+    if has_csproj_or_sln {
+        return config::Language::Csharp;
+    }
 
     config::Language::Typescript
 }
 
+/// Formats strings as TOML array, which is empty for no strings.
+fn toml_list(items: &[String]) -> String {
+    if items.is_empty() {
+        return "[]".to_string();
+    }
+    format!("[\"{}\"]", items.join("\", \""))
+}
+
 pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
     let folder = if let Some(path) = path {
         path
@@ -45,6 +94,14 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
            &Language::Python,
            &Language::Kotlin,
            &Language::Golang,
+           &Language::Rust,
+           &Language::Java,
+           &Language::Deno,
+           &Language::Bun,
+           &Language::Csharp,
+           &Language::Ruby,
+           &Language::Php,
+           &Language::Elixir,
         ];
 
         // sort to put detected language first
@@ -73,7 +130,8 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
         r####"
 # language is used to determine the default paths to watch for changes
 # and the default command to run the server.
-# Possible values are "typescript", "python", "kotlin" and "golang"
+# Possible values are "typescript", "python", "kotlin", "golang", "rust",
+# "java", "deno", "bun", "csharp", "ruby", "php" and "elixir"
 "####,
     );
 
@@ -90,9 +148,33 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
         config::Language::Golang => {
             conf_buf.push_str(r#"language = "golang""#);
         }
+        config::Language::Rust => {
+            conf_buf.push_str(r#"language = "rust""#);
+        }
+        config::Language::Java => {
+            conf_buf.push_str(r#"language = "java""#);
+        }
+        config::Language::Deno => {
+            conf_buf.push_str(r#"language = "deno""#);
+        }
+        config::Language::Bun => {
+            conf_buf.push_str(r#"language = "bun""#);
+        }
+        config::Language::Csharp => {
+            conf_buf.push_str(r#"language = "csharp""#);
+        }
+        config::Language::Ruby => {
+            conf_buf.push_str(r#"language = "ruby""#);
+        }
+        config::Language::Php => {
+            conf_buf.push_str(r#"language = "php""#);
+        }
+        config::Language::Elixir => {
+            conf_buf.push_str(r#"language = "elixir""#);
+        }
     }
 
-    conf_buf.push_str("\n");
+    conf_buf.push('\n');
 
     conf_buf.push_str(r#"
 # Resending resource subscriptions can be enabled to make synf
@@ -100,6 +182,30 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
 # and later resend them after server restart, defaults to false
 # resend_resource_subscriptions = false
 
+# Build gate can be enabled to keep previous server running when build fails,
+# or when the new server fails to start or to initialize, so that client
+# would continue working with previous version until next successful build,
+# defaults to false
+# build_gate = false
+
+# Requests that server has not responded to before restart are answered with error,
+# replaying can be enabled to send them again to the restarted server instead,
+# which is only done when synf has restarted server itself and not after crash,
+# defaults to false
+# replay_in_flight_requests = false
+
+# When client closes stdin, synf waits up to disconnect_grace_ms for server to respond
+# to requests that client has sent, then stops server and exits, defaults to 2000
+# disconnect_grace_ms = 2000
+
+# Environment variables can be set for build and run commands with env_file (dotenv file
+# relative to the project folder) and env table, which overrides variables from env_file.
+# ${VAR} in values is replaced by variable from environment of synf. Variables set here apply
+# to both commands, [build] and [run] can set their own, which override these.
+# Changes in env files rebuild server, or only restart it for env_file in [run].
+# env_file = ".env"
+# env = { PYTHONUNBUFFERED = "1", API_KEY = "${API_KEY}" }
+
 "#);
 
     conf_buf.push_str(&format!(
@@ -109,17 +215,49 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
 "
     ));
 
-    let (build_command, build_args) = runner::Runner::get_build_command(&language);
+    let (build_command, build_args) = runner::Runner::get_build_command(&language, path);
 
     conf_buf.push_str(&format!(
         r#"
 # command = "{}"
-# args = ["{}"]
+# args = {}
 "#,
         build_command,
-        build_args.join("\", \"")
+        toml_list(&build_args)
     ));
 
+    conf_buf.push_str(
+        r#"
+# Build output is printed to stderr with [build] prefix and can be saved to log_file,
+# which must not be in watched paths. When build fails, last failure_lines of output
+# are sent to client as error log message, 0 disables that, defaults to 20.
+# log_file = "synf-build.log"
+# failure_lines = 20
+
+# env_file = ".env.build"
+# env = { NODE_ENV = "development" }
+
+# cwd is used to specify directory to run command in, relative to the project folder.
+# shell can be enabled to run command with args as a command line of sh -c (cmd /C on Windows),
+# so that pipes, && chains and script shims work, where command is used as is
+# and each of args is quoted (args with " or % are rejected on Windows), defaults to false
+# cwd = "server"
+# shell = true
+
+# steps can be used instead of command and args to run several commands in sequence,
+# each with name, command, args, cwd, shell, env, env_file and continue_on_error.
+# Build stops at the first failing step, unless it has continue_on_error = true.
+# [[build.steps]]
+# name = "codegen"
+# command = "npm"
+# args = ["run", "codegen"]
+# [[build.steps]]
+# name = "compile"
+# command = "npm"
+# args = ["run", "build"]
+"#,
+    );
+
     conf_buf.push_str(&format!(
         r#"
 [run]
@@ -129,17 +267,67 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
 "#
     ));
 
-    let (run_command, run_args) = runner::Runner::get_run_command(&language);
+    let (run_command, run_args) = runner::Runner::get_run_command(&language, path);
+    // binary of rust project is found as absolute path
+    let run_command = match path.canonicalize() {
+        Ok(folder) => match Path::new(&run_command).strip_prefix(folder) {
+            Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+            Err(_) => run_command,
+        },
+        Err(_) => run_command,
+    };
 
     conf_buf.push_str(&format!(
         r#"
 # command = "{}"
-# args = ["{}"]
+# args = {}
 "#,
         run_command,
-        run_args.join("\", \"")
+        toml_list(&run_args)
     ));
 
+    conf_buf.push_str(
+        r#"
+# transport is used to specify how server talks to synf, possible values are
+# "stdio" (default), "http" for Streamable HTTP and "sse" for legacy HTTP with SSE.
+# For "http" and "sse" url of the endpoint is required and synf would wait
+# until server accepts connections, up to ready_timeout_ms (defaults to 30000).
+# transport = "http"
+# url = "http://127.0.0.1:8000/mcp"
+# ready_timeout_ms = 30000
+
+# initialize_timeout_ms is used to specify how long to wait for server to respond to initialize,
+# server that does not respond in time is stopped and restarted as crashed, defaults to 30000
+# initialize_timeout_ms = 30000
+
+# env_file = ".env.run"
+# env = { LOG_LEVEL = "debug" }
+
+# cwd = "server"
+# shell = true
+
+# Lines that server prints to stdout, which are not JSON-RPC messages, are rerouted
+# to stderr with a warning. strict_stdout stops server instead, like it has crashed,
+# notify_stdout_pollution sends the warnings to client as log messages too.
+# strict_stdout = false
+# notify_stdout_pollution = false
+
+# shutdown_grace_ms is used to specify how long to wait for server to exit on its own
+# after its stdin is closed, before sending SIGTERM to its process group, defaults to 2000.
+# kill_timeout_ms is used to specify how long to wait after that before sending SIGKILL,
+# defaults to 5000
+# shutdown_grace_ms = 2000
+# kill_timeout_ms = 5000
+
+# Server that exits on its own is restarted after crash_backoff_ms, which doubles for every
+# next crash in a row, up to 30 seconds. After max_crash_restarts restarts of server that keeps
+# crashing within 30 seconds after start, it is not restarted until next change.
+# restart_on_crash = true
+# max_crash_restarts = 5
+# crash_backoff_ms = 500
+"#,
+    );
+
     conf_buf.push_str(&format!(
         r#"
 [watch]
@@ -153,22 +341,70 @@ pub(crate) fn run(path: Option<String>) -> eyre::Result<()> {
 "#
     ));
 
-    let default_paths = runner::Runner::get_default_watch_paths(&language);
+    let default_paths = runner::Runner::get_default_watch_paths(&language, path);
 
     conf_buf.push_str(&format!(
         r#"
-# default_paths = ["{}"]
+# default_paths = {}
 "#,
-        default_paths.join("\", \"")
+        toml_list(&default_paths)
     ));
 
-    conf_buf.push_str(&format!(
+    conf_buf.push_str(
         r#"
 # extra_paths are the paths that are watched in addition to the default paths.
 # You can use it to add more paths to watch for changes besides the default paths.
 # extra_paths = []
-"#
-    ));
+
+# include and exclude are glob patterns for paths relative to the project folder,
+# only changes in paths matching include (if set) and not matching exclude reload server.
+# include = ["src/**/*.ts"]
+# exclude = ["**/*.swp", "**/__pycache__/**", "**/node_modules/.cache/**"]
+"#,
+    );
+
+    let (default_include, default_exclude) = runner::Runner::get_default_watch_filter(&language);
+    if !default_include.is_empty() || !default_exclude.is_empty() {
+        conf_buf.push_str(&format!(
+            r#"# These are the patterns that are used by default for {language},
+# where include is not used when extra_paths or rules are set:
+# include = {}
+# exclude = {}
+"#,
+            toml_list(&default_include),
+            toml_list(&default_exclude)
+        ));
+    }
+
+    conf_buf.push_str(
+        r#"
+# gitignore can be enabled to ignore changes in paths ignored by .gitignore and .ignore files,
+# defaults to false
+# gitignore = true
+
+# mode is used to specify how changes are detected, possible values are "native" (default),
+# which falls back to polling for paths that cannot be watched natively, and "poll",
+# which works on Docker bind mounts, WSL, NFS and other filesystems without change events.
+# mode = "poll"
+# poll_interval_ms = 1000
+
+# debounce_ms is used to specify how long to wait for changes to settle before reloading,
+# changes that come while server is being reloaded cause exactly one more reload,
+# defaults to 2000
+# debounce_ms = 2000
+
+# rules can be used to do something else than rebuilding server for changes in paths
+# matching glob, relative to the project folder. Possible actions are "rebuild",
+# "restart" (without build), "notify_resources" (send notifications/resources/updated
+# with uri, where {path} and {absolute_path} are replaced by path of changed file,
+# defaults to "file://{absolute_path}", if client has subscribed to it) and "run" (run command with args).
+# The strongest action among changed paths is used, where paths matching no rule rebuild.
+# [[watch.rules]]
+# glob = "prompts/**"
+# action = "notify_resources"
+# uri = "prompt://{path}"
+"#,
+    );
 
     let path = path.join("synf.toml");
 
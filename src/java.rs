@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+
+use eyre::Context;
+
+/// Tool that builds java project.
+#[derive(Debug, PartialEq)]
+pub(crate) enum BuildTool {
+    Maven,
+    Gradle,
+}
+
+pub(crate) fn build_tool(project: &Path) -> BuildTool {
+    if project.join("pom.xml").exists() {
+        BuildTool::Maven
+    } else {
+        BuildTool::Gradle
+    }
+}
+
+/// Jar that `mvn package` makes, relative to project.
+pub(crate) fn maven_jar(project: &Path) -> eyre::Result<PathBuf> {
+    let path = project.join("pom.xml");
+    let pom =
+        std::fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+    let elements = top_level_elements(&pom);
+    let find = |name: &str| {
+        elements
+            .iter()
+            .find(|(element, _)| element == name)
+            .map(|(_, text)| text.clone())
+    };
+
+    let artifact_id = find("project/artifactId")
+        .ok_or_else(|| eyre::eyre!("artifactId is missing in {:?}", path))?;
+    // version can be inherited from parent
+    let version = find("project/version")
+        .or_else(|| find("project/parent/version"))
+        .ok_or_else(|| eyre::eyre!("version is missing in {:?}", path))?;
+    let name = find("project/build/finalName")
+        .unwrap_or_else(|| format!("{}-{}", artifact_id, version))
+        .replace("${project.artifactId}", &artifact_id)
+        .replace("${project.version}", &version);
+    if name.contains("${") {
+        return Err(eyre::eyre!(
+            "name of jar {:?} in {:?} uses properties that synf does not resolve",
+            name,
+            path
+        ));
+    }
+    Ok(PathBuf::from("target").join(format!("{}.jar", name)))
+}
+
+/// Script that `./gradlew installDist` makes, relative to project.
+pub(crate) fn gradle_script(project: &Path) -> PathBuf {
+    let name = ["settings.gradle.kts", "settings.gradle"]
+        .iter()
+        .filter_map(|settings| std::fs::read_to_string(project.join(settings)).ok())
+        .find_map(|settings| root_project_name(&settings))
+        .or_else(|| {
+            // gradle names project after its folder by default
+            project
+                .canonicalize()
+                .ok()?
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+    let script = if cfg!(windows) {
+        format!("{}.bat", name)
+    } else {
+        name.clone()
+    };
+    PathBuf::from("build")
+        .join("install")
+        .join(name)
+        .join("bin")
+        .join(script)
+}
+
+/// Finds `rootProject.name = "name"` in gradle settings.
+fn root_project_name(settings: &str) -> Option<String> {
+    settings.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("rootProject.name")?;
+        let value = value.trim_start().strip_prefix('=')?.trim();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = value[1..].split(quote).next()?;
+        Some(value.to_string())
+    })
+}
+
+/// Texts of elements in pom that are not nested in dependencies, plugins
+/// and other lists, by their paths, f.e "project/build/finalName".
+fn top_level_elements(xml: &str) -> Vec<(String, String)> {
+    let mut elements = Vec::new();
+    let mut path: Vec<&str> = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let text = rest[..start].trim();
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.split_once("-->").map_or("", |(_, rest)| rest);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') || tag.ends_with('/') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            if path.last() == Some(&name.trim()) {
+                if path.len() <= 3 && !text.is_empty() {
+                    elements.push((path.join("/"), text.to_string()));
+                }
+                path.pop();
+            }
+            continue;
+        }
+        let name = tag.split_whitespace().next().unwrap_or_default();
+        path.push(name);
+    }
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(path: &str, text: &str) -> (String, String) {
+        (path.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn finds_top_level_elements_of_pom() {
+        let pom = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project xmlns="http://maven.apache.org/POM/4.0.0">
+  <modelVersion>4.0.0</modelVersion>
+  <parent>
+    <groupId>org.example</groupId>
+    <version>2.0</version>
+  </parent>
+  <artifactId>server</artifactId>
+  <dependencies>
+    <dependency>
+      <artifactId>library</artifactId>
+      <version>9.9</version>
+    </dependency>
+  </dependencies>
+  <build>
+    <finalName>${project.artifactId}-app</finalName>
+    <plugins>
+      <plugin>
+        <artifactId>plugin</artifactId>
+        <configuration><finalName>ignored</finalName></configuration>
+      </plugin>
+    </plugins>
+  </build>
+</project>
+"#;
+        assert_eq!(
+            top_level_elements(pom),
+            vec![
+                element("project/modelVersion", "4.0.0"),
+                element("project/parent/groupId", "org.example"),
+                element("project/parent/version", "2.0"),
+                element("project/artifactId", "server"),
+                element("project/build/finalName", "${project.artifactId}-app"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_comments_and_empty_elements() {
+        let pom = r#"<project>
+  <!-- <version>0.1</version> -->
+  <artifactId>server</artifactId><!--
+  <artifactId>old</artifactId>
+  -->
+  <packaging/>
+  <version>1.0</version>
+</project>"#;
+        assert_eq!(
+            top_level_elements(pom),
+            vec![
+                element("project/artifactId", "server"),
+                element("project/version", "1.0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_root_project_name() {
+        assert_eq!(
+            root_project_name("plugins {}\nrootProject.name = \"server\"\n"),
+            Some("server".to_string())
+        );
+        assert_eq!(
+            root_project_name("rootProject.name='server' // comment"),
+            Some("server".to_string())
+        );
+        assert_eq!(root_project_name("include(\"app\")"), None);
+    }
+}
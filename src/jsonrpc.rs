@@ -0,0 +1,288 @@
+use serde_json::{json, Map, Value};
+
+/// Error code used by synf when responding to requests instead of server.
+pub(crate) const SERVER_ERROR: i64 = -32000;
+pub(crate) const INTERNAL_ERROR: i64 = -32603;
+
+/// Single JSON-RPC message.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Message {
+    Request {
+        id: Value,
+        method: String,
+        params: Option<Value>,
+    },
+    Notification {
+        method: String,
+        params: Option<Value>,
+    },
+    Response {
+        id: Value,
+        outcome: Outcome,
+    },
+}
+
+/// Either result or error object of the response.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Outcome {
+    Result(Value),
+    Error(Value),
+}
+
+/// Whatever was sent on one line, which is either single message or batch of them.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Frame {
+    Single(Message),
+    Batch(Vec<Message>),
+}
+
+impl Frame {
+    pub(crate) fn messages(&self) -> &[Message] {
+        match self {
+            Frame::Single(message) => std::slice::from_ref(message),
+            Frame::Batch(messages) => messages,
+        }
+    }
+}
+
+/// Parses and validates one line of newline delimited JSON-RPC stream.
+pub(crate) fn parse(line: &str) -> eyre::Result<Frame> {
+    let value = serde_json::from_str::<Value>(line)
+        .map_err(|e| eyre::eyre!("message is not valid JSON: {}", e))?;
+    match value {
+        Value::Array(values) => {
+            if values.is_empty() {
+                return Err(eyre::eyre!("batch is empty"));
+            }
+            let messages = values
+                .into_iter()
+                .map(Message::from_value)
+                .collect::<eyre::Result<Vec<_>>>()?;
+            Ok(Frame::Batch(messages))
+        }
+        value => Ok(Frame::Single(Message::from_value(value)?)),
+    }
+}
+
+impl Message {
+    pub(crate) fn from_value(value: Value) -> eyre::Result<Message> {
+        let Value::Object(mut object) = value else {
+            return Err(eyre::eyre!("message is not an object"));
+        };
+        if object.get("jsonrpc").and_then(|version| version.as_str()) != Some("2.0") {
+            return Err(eyre::eyre!("message does not declare jsonrpc version 2.0"));
+        }
+
+        let id = object.remove("id");
+        if let Some(id) = &id {
+            if !(id.is_string() || id.is_number() || id.is_null()) {
+                return Err(eyre::eyre!("id must be string, number or null"));
+            }
+        }
+
+        if let Some(method) = object.remove("method") {
+            let Value::String(method) = method else {
+                return Err(eyre::eyre!("method must be a string"));
+            };
+            let params = object.remove("params");
+            if let Some(params) = &params {
+                if !(params.is_object() || params.is_array()) {
+                    return Err(eyre::eyre!("params must be an object or an array"));
+                }
+            }
+            return Ok(match id {
+                Some(id) => Message::Request { id, method, params },
+                None => Message::Notification { method, params },
+            });
+        }
+
+        let Some(id) = id else {
+            return Err(eyre::eyre!("message has neither method nor id"));
+        };
+        let outcome = match (object.remove("result"), object.remove("error")) {
+            (Some(result), None) => Outcome::Result(result),
+            (None, Some(error)) => {
+                if !error.get("code").is_some_and(|code| code.is_i64()) {
+                    return Err(eyre::eyre!("error must have integer code"));
+                }
+                Outcome::Error(error)
+            }
+            _ => {
+                return Err(eyre::eyre!(
+                    "response must have either result or error, but not both"
+                ))
+            }
+        };
+        Ok(Message::Response { id, outcome })
+    }
+
+    pub(crate) fn id(&self) -> Option<&Value> {
+        match self {
+            Message::Request { id, .. } | Message::Response { id, .. } => Some(id),
+            Message::Notification { .. } => None,
+        }
+    }
+
+    /// Checks if this is the response to request with given id.
+    pub(crate) fn is_response_to(&self, request_id: &Value) -> bool {
+        matches!(self, Message::Response { id, .. } if id == request_id)
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("jsonrpc".to_string(), json!("2.0"));
+        if let Some(id) = self.id() {
+            object.insert("id".to_string(), id.clone());
+        }
+        match self {
+            Message::Request { method, params, .. } | Message::Notification { method, params } => {
+                object.insert("method".to_string(), json!(method));
+                if let Some(params) = params {
+                    object.insert("params".to_string(), params.clone());
+                }
+            }
+            Message::Response { outcome, .. } => match outcome {
+                Outcome::Result(result) => {
+                    object.insert("result".to_string(), result.clone());
+                }
+                Outcome::Error(error) => {
+                    object.insert("error".to_string(), error.clone());
+                }
+            },
+        }
+        Value::Object(object)
+    }
+}
+
+/// Key to look up messages by their id, so that `1` and `"1"` are not confused.
+pub(crate) fn id_key(id: &Value) -> String {
+    id.to_string()
+}
+
+/// Builds log message notification from synf to client.
+pub(crate) fn log_notification(level: &str, data: &str) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/message",
+        "params": {"level": level, "logger": "synf", "data": data},
+    })
+    .to_string()
+}
+
+/// Builds error response to request with given id.
+pub(crate) fn error_response(id: &Value, code: i64, message: &str) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message},
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_notification_and_response() {
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":1,"method":"tools/list","params":{}}"#).unwrap(),
+            Frame::Single(Message::Request {
+                id: json!(1),
+                method: "tools/list".to_string(),
+                params: Some(json!({})),
+            })
+        );
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#).unwrap(),
+            Frame::Single(Message::Notification {
+                method: "notifications/initialized".to_string(),
+                params: None,
+            })
+        );
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":"a","result":{"tools":[]}}"#).unwrap(),
+            Frame::Single(Message::Response {
+                id: json!("a"),
+                outcome: Outcome::Result(json!({"tools": []})),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_error_response() {
+        let frame =
+            parse(r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32601,"message":"nope"}}"#).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Single(Message::Response {
+                id: json!(2),
+                outcome: Outcome::Error(json!({"code": -32601, "message": "nope"})),
+            })
+        );
+        assert!(frame.messages()[0].is_response_to(&json!(2)));
+        assert!(!frame.messages()[0].is_response_to(&json!("2")));
+    }
+
+    #[test]
+    fn parses_batch() {
+        let frame = parse(
+            r#"[{"jsonrpc":"2.0","id":1,"method":"ping"},{"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId":0}}]"#,
+        )
+        .unwrap();
+        let Frame::Batch(messages) = &frame else {
+            panic!("expected batch, got {:?}", frame);
+        };
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id(), Some(&json!(1)));
+        assert_eq!(messages[1].id(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_messages() {
+        for line in [
+            "not json",
+            "[]",
+            "42",
+            r#"{"id":1,"method":"ping"}"#,
+            r#"{"jsonrpc":"1.0","id":1,"method":"ping"}"#,
+            r#"{"jsonrpc":"2.0","id":{"a":1},"method":"ping"}"#,
+            r#"{"jsonrpc":"2.0","id":[1],"result":{}}"#,
+            r#"{"jsonrpc":"2.0","id":true,"method":"ping"}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":2}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping","params":"x"}"#,
+            r#"{"jsonrpc":"2.0","result":{}}"#,
+            r#"{"jsonrpc":"2.0","id":1}"#,
+            r#"{"jsonrpc":"2.0","id":1,"result":{},"error":{"code":1}}"#,
+            r#"{"jsonrpc":"2.0","id":1,"error":{"message":"no code"}}"#,
+            r#"[{"jsonrpc":"2.0","id":1,"method":"ping"},{"jsonrpc":"2.0"}]"#,
+        ] {
+            assert!(parse(line).is_err(), "{} should be invalid", line);
+        }
+    }
+
+    #[test]
+    fn accepts_null_id() {
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse error"}}"#)
+                .unwrap()
+                .messages()[0]
+                .id(),
+            Some(&Value::Null)
+        );
+    }
+
+    #[test]
+    fn converts_back_to_value() {
+        let value =
+            json!({"jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": {"name": "t"}});
+        let message = Message::from_value(value.clone()).unwrap();
+        assert_eq!(message.to_value(), value);
+    }
+
+    #[test]
+    fn id_keys_distinguish_numbers_and_strings() {
+        assert_ne!(id_key(&json!(1)), id_key(&json!("1")));
+        assert_eq!(id_key(&json!(1)), id_key(&json!(1)));
+    }
+}
@@ -0,0 +1,249 @@
+use serde_json::{json, Value};
+
+use crate::capabilities;
+
+/// Result of listing items of one kind on server.
+#[derive(PartialEq, Debug)]
+pub(crate) enum Listed {
+    Items(Vec<Value>),
+    /// Server responded with error, f.e because it does not support listing.
+    Unsupported,
+    /// Server could not be asked, so it is not known what it has.
+    Unknown,
+}
+
+/// Everything server lists, which clients are notified about with list_changed.
+#[derive(Debug)]
+pub(crate) struct Lists {
+    tools: Listed,
+    prompts: Listed,
+    resources: Listed,
+    resource_templates: Listed,
+}
+
+/// Lists everything on server, following pagination cursors.
+///
+/// Request function sends request with given method and params to server
+/// and returns whole JSON-RPC response, or error if it could not get one.
+/// Only lists for capabilities declared by server are requested.
+pub(crate) fn query(
+    request: &mut impl FnMut(&str, Value) -> eyre::Result<Value>,
+    server_capabilities: Option<&Value>,
+) -> Lists {
+    let mut reachable = true;
+    let mut list = |method: &str, field: &str| {
+        let capability = method.split('/').next().unwrap_or(method);
+        if !capabilities::declares(server_capabilities, capability) {
+            return Listed::Unsupported;
+        }
+        if !reachable {
+            return Listed::Unknown;
+        }
+        let listed = list_all(request, method, field);
+        if listed == Listed::Unknown {
+            // do not wait for every other request to time out as well
+            reachable = false;
+        }
+        listed
+    };
+
+    Lists {
+        tools: list("tools/list", "tools"),
+        prompts: list("prompts/list", "prompts"),
+        resources: list("resources/list", "resources"),
+        resource_templates: list("resources/templates/list", "resourceTemplates"),
+    }
+}
+
+fn list_all(
+    request: &mut impl FnMut(&str, Value) -> eyre::Result<Value>,
+    method: &str,
+    field: &str,
+) -> Listed {
+    let mut items = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let params = match &cursor {
+            Some(cursor) => json!({ "cursor": cursor }),
+            None => json!({}),
+        };
+        let response = match request(method, params) {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Failed to list {}: {:#}", field, e);
+                return Listed::Unknown;
+            }
+        };
+        let Some(result) = response.get("result") else {
+            return Listed::Unsupported;
+        };
+        if let Some(page) = result.get(field).and_then(|page| page.as_array()) {
+            items.extend(page.iter().cloned());
+        }
+        match result.get("nextCursor").and_then(|next| next.as_str()) {
+            Some(next) if cursor.as_deref() != Some(next) => cursor = Some(next.to_string()),
+            _ => return Listed::Items(items),
+        }
+    }
+}
+
+fn differ(previous: &Listed, current: &Listed) -> bool {
+    *previous == Listed::Unknown || *current == Listed::Unknown || previous != current
+}
+
+/// Returns list_changed notifications for lists that differ between servers,
+/// all of them if lists of previous server are not known.
+/// Notifications are only returned for capabilities that client was told
+/// support `listChanged`.
+pub(crate) fn changed_notifications(
+    previous: Option<&Lists>,
+    current: &Lists,
+    client_capabilities: Option<&Value>,
+) -> Vec<&'static str> {
+    let all = Lists {
+        tools: Listed::Unknown,
+        prompts: Listed::Unknown,
+        resources: Listed::Unknown,
+        resource_templates: Listed::Unknown,
+    };
+    let previous = previous.unwrap_or(&all);
+
+    let declares = |name| capabilities::declares_list_changed(client_capabilities, name);
+    let mut notifications = Vec::new();
+    if declares("tools") && differ(&previous.tools, &current.tools) {
+        notifications
+            .push(r####"{"method":"notifications/tools/list_changed","jsonrpc":"2.0"}"####);
+    }
+    if declares("prompts") && differ(&previous.prompts, &current.prompts) {
+        notifications
+            .push(r####"{"method":"notifications/prompts/list_changed","jsonrpc":"2.0"}"####);
+    }
+    if declares("resources")
+        && (differ(&previous.resources, &current.resources)
+            || differ(&previous.resource_templates, &current.resource_templates))
+    {
+        notifications
+            .push(r####"{"method":"notifications/resources/list_changed","jsonrpc":"2.0"}"####);
+    }
+    notifications
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists(tools: &[&str], templates: &[&str]) -> Lists {
+        let items = |names: &[&str]| {
+            Listed::Items(names.iter().map(|name| json!({ "name": name })).collect())
+        };
+        Lists {
+            tools: items(tools),
+            prompts: items(&[]),
+            resources: items(&[]),
+            resource_templates: items(templates),
+        }
+    }
+
+    const TOOLS: &str = r#"{"method":"notifications/tools/list_changed","jsonrpc":"2.0"}"#;
+    const PROMPTS: &str = r#"{"method":"notifications/prompts/list_changed","jsonrpc":"2.0"}"#;
+    const RESOURCES: &str = r#"{"method":"notifications/resources/list_changed","jsonrpc":"2.0"}"#;
+
+    #[test]
+    fn notifies_about_everything_when_previous_lists_are_unknown() {
+        let current = lists(&["t"], &[]);
+        assert_eq!(
+            changed_notifications(None, &current, None),
+            vec![TOOLS, PROMPTS, RESOURCES]
+        );
+        let unknown = Lists {
+            tools: Listed::Unknown,
+            ..lists(&["t"], &[])
+        };
+        assert_eq!(
+            changed_notifications(Some(&unknown), &current, None),
+            vec![TOOLS]
+        );
+    }
+
+    #[test]
+    fn does_not_notify_about_unchanged_lists() {
+        let previous = lists(&["t"], &["file://{path}"]);
+        let current = lists(&["t"], &["file://{path}"]);
+        assert!(changed_notifications(Some(&previous), &current, None).is_empty());
+    }
+
+    #[test]
+    fn notifies_about_resources_when_only_templates_change() {
+        let previous = lists(&["t"], &["file://{path}"]);
+        let current = lists(&["t"], &["file://{path}", "db://{table}"]);
+        assert_eq!(
+            changed_notifications(Some(&previous), &current, None),
+            vec![RESOURCES]
+        );
+    }
+
+    #[test]
+    fn only_notifies_about_capabilities_with_list_changed() {
+        let capabilities = json!({
+            "tools": {"listChanged": true},
+            "prompts": {},
+            "resources": {"subscribe": true, "listChanged": false},
+        });
+        assert_eq!(
+            changed_notifications(None, &lists(&["t"], &[]), Some(&capabilities)),
+            vec![TOOLS]
+        );
+    }
+
+    #[test]
+    fn follows_pagination_until_cursor_repeats() {
+        let mut requests = Vec::new();
+        let mut request = |method: &str, params: Value| {
+            requests.push((method.to_string(), params.clone()));
+            Ok(
+                match params.get("cursor").and_then(|cursor| cursor.as_str()) {
+                    None => json!({"result": {"tools": [{"name": "a"}], "nextCursor": "2"}}),
+                    Some("2") => json!({"result": {"tools": [{"name": "b"}], "nextCursor": "3"}}),
+                    // buggy server returning the same cursor again
+                    Some(_) => json!({"result": {"tools": [{"name": "c"}], "nextCursor": "3"}}),
+                },
+            )
+        };
+        assert_eq!(
+            list_all(&mut request, "tools/list", "tools"),
+            Listed::Items(vec![
+                json!({"name": "a"}),
+                json!({"name": "b"}),
+                json!({"name": "c"}),
+            ])
+        );
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].1, json!({"cursor": "3"}));
+    }
+
+    #[test]
+    fn queries_only_declared_capabilities_until_server_is_unreachable() {
+        let mut methods = Vec::new();
+        let capabilities = json!({"tools": {}, "prompts": {}, "resources": {}});
+        let lists = query(
+            &mut |method, _| {
+                methods.push(method.to_string());
+                match method {
+                    "tools/list" => Ok(json!({"error": {"code": -32601, "message": "no"}})),
+                    _ => Err(eyre::eyre!("timed out")),
+                }
+            },
+            Some(&capabilities),
+        );
+        assert_eq!(lists.tools, Listed::Unsupported);
+        assert_eq!(lists.prompts, Listed::Unknown);
+        assert_eq!(lists.resources, Listed::Unknown);
+        assert_eq!(lists.resource_templates, Listed::Unknown);
+        // requests after the first one that timed out are not sent
+        assert_eq!(methods, vec!["tools/list", "prompts/list"]);
+
+        let lists = query(&mut |_, _| Err(eyre::eyre!("unused")), Some(&json!({})));
+        assert_eq!(lists.tools, Listed::Unsupported);
+        assert_eq!(lists.resource_templates, Listed::Unsupported);
+    }
+}
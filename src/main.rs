@@ -1,12 +1,32 @@
-use std::{path::Path, sync::mpsc};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
 use argh::FromArgs;
+use crossbeam_channel::{select, unbounded};
 use eyre::Context;
 
+mod build;
+mod capabilities;
+mod cargo;
+mod client;
 mod config;
+mod control;
+mod dotnet;
+mod env;
+mod http;
+mod http_client;
+mod http_server;
 mod init;
+mod java;
+mod jsonrpc;
+mod lists;
+mod process;
 mod runner;
+mod transport;
 mod utils;
+mod watch;
 
 #[derive(FromArgs)]
 /// Tool for MCP Server hot reloading 
@@ -20,6 +40,7 @@ struct Synf {
 enum Subcommand {
     Dev(Dev),
     Init(Init),
+    Reload(Reload),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -28,6 +49,11 @@ enum Subcommand {
 struct Dev {
     #[argh(positional)]
     path: Option<String>,
+
+    /// expose server over Streamable HTTP on given address, f.e 127.0.0.1:3000,
+    /// instead of talking to client over stdio
+    #[argh(option)]
+    listen: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -38,6 +64,22 @@ struct Init {
     path: Option<String>,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+/// Ask synf dev running in project to rebuild and restart server
+#[argh(subcommand, name = "reload")]
+struct Reload {
+    #[argh(positional)]
+    path: Option<String>,
+
+    /// only run build, without restarting server
+    #[argh(switch)]
+    build_only: bool,
+
+    /// restart server without running build
+    #[argh(switch)]
+    no_build: bool,
+}
+
 fn main() -> color_eyre::eyre::Result<()> {
     color_eyre::install()?;
     let synf: Synf = argh::from_env();
@@ -45,7 +87,7 @@ fn main() -> color_eyre::eyre::Result<()> {
         Subcommand::Init(Init { path }) => {
             return init::run(path);
         }
-        Subcommand::Dev(Dev { path }) => {
+        Subcommand::Dev(Dev { path, listen }) => {
             let folder = if let Some(path) = path {
                 path
             } else {
@@ -57,17 +99,88 @@ fn main() -> color_eyre::eyre::Result<()> {
             let cfg =
                 config::read_from_toml(path).context("failed to read config from synf.toml")?;
 
-            runner::Runner::new(path.to_path_buf(), cfg)?;
+            let client = match listen {
+                Some(addr) => http_server::listen(&addr)?,
+                None => client::stdio(),
+            };
 
-            let (tx, rx) = mpsc::channel::<()>();
+            // set before server is started, as it would not receive Ctrl-C
+            // from terminal in its own process group
+            let (tx, rx) = unbounded::<()>();
 
             ctrlc::set_handler(move || tx.send(()).expect("Could not send signal on channel."))
                 .expect("Error setting Ctrl-C handler");
 
+            let disconnected = client.disconnected.clone();
+            let runner = runner::Runner::new(path.to_path_buf(), cfg, client)?;
+
+            reload_on_hangup(runner.clone())?;
+            let control = match control::listen(path, runner.clone()) {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("synf reload would not work: {:#}", e);
+                    false
+                }
+            };
+
             eprintln!("Use Ctrl-C to exit.");
-            rx.recv().expect("Could not receive from stopping channel.");
+            select! {
+                recv(rx) -> _ => {
+                    eprintln!("Stopping server");
+                    runner.lock().unwrap().shutdown();
+                }
+                recv(disconnected) -> _ => {
+                    eprintln!("Client has disconnected, stopping server");
+                    runner.lock().unwrap().disconnect();
+                }
+            }
+            if control {
+                control::cleanup(path);
+            }
+        }
+        Subcommand::Reload(Reload {
+            path,
+            build_only,
+            no_build,
+        }) => {
+            let folder = path.unwrap_or_else(|| String::from("."));
+            let path = Path::new(&folder);
+            utils::validate_path(path)?;
+
+            let reload = match (build_only, no_build) {
+                (true, true) => {
+                    return Err(eyre::eyre!(
+                        "--build-only and --no-build cannot be used together"
+                    ))
+                }
+                (true, false) => runner::Reload::BuildOnly,
+                (false, true) => runner::Reload::RestartOnly,
+                (false, false) => runner::Reload::Full,
+            };
+            control::send(path, reload)?;
+            eprintln!("Done");
         }
     }
 
     Ok(())
 }
+
+/// Rebuilds and restarts server when synf receives SIGHUP.
+#[cfg(unix)]
+fn reload_on_hangup(runner: Arc<Mutex<runner::Runner>>) -> eyre::Result<()> {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP]).context("failed to handle SIGHUP")?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            eprintln!("Received SIGHUP, reloading");
+            runner.lock().unwrap().trigger();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reload_on_hangup(_runner: Arc<Mutex<runner::Runner>>) -> eyre::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use std::{
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        os::unix::process::CommandExt,
+        process::{Child, Command},
+    };
+
+    pub(super) fn new_group(command: &mut Command) {
+        // group id is the same as process id of its leader
+        command.process_group(0);
+    }
+
+    pub(super) fn signal_group(child: &Child, signal: libc::c_int) -> bool {
+        let group = child.id() as libc::pid_t;
+        // SAFETY: kill has no memory safety requirements, negative pid means group
+        unsafe { libc::kill(-group, signal) == 0 }
+    }
+}
+
+fn is_plain(arg: &str) -> bool {
+    !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-+=@:,./".contains(c))
+}
+
+/// Quotes argument for `sh -c`, where nothing is interpreted in single quotes.
+fn quote_sh(arg: &str) -> String {
+    if is_plain(arg) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Quotes argument for `cmd /C`, which does not interpret `&`, `|`, `<` and `>`
+/// in double quotes, but has no way to escape quotes in them and expands
+/// `%VAR%` everywhere, so arguments with those are rejected.
+fn quote_cmd(arg: &str) -> eyre::Result<String> {
+    if is_plain(arg) {
+        return Ok(arg.to_string());
+    }
+    if let Some(c) = arg.chars().find(|c| matches!(c, '"' | '%' | '\r' | '\n')) {
+        return Err(eyre::eyre!(
+            "argument {:?} has {:?}, which cannot be passed through cmd, put it into command instead",
+            arg,
+            c
+        ));
+    }
+    // backslashes before closing quote would escape it for program
+    let trailing = arg.len() - arg.trim_end_matches('\\').len();
+    Ok(format!("\"{}{}\"", arg, "\\".repeat(trailing)))
+}
+
+/// Creates command that runs program with args, or their command line with platform
+/// shell, f.e to use pipes or scripts that are not executables. Program is put into
+/// command line as is, so it can be the whole line, while args are quoted.
+pub(crate) fn new_command(program: &str, args: &[String], shell: bool) -> eyre::Result<Command> {
+    if !shell {
+        let mut command = Command::new(program);
+        command.args(args);
+        return Ok(command);
+    }
+    let mut line = program.to_string();
+    for arg in args {
+        line.push(' ');
+        if cfg!(windows) {
+            line.push_str(&quote_cmd(arg)?);
+        } else {
+            line.push_str(&quote_sh(arg));
+        }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let mut command = Command::new("cmd");
+        // cmd has its own rules for quotes, so line is passed as is
+        command.arg("/C").raw_arg(line);
+        Ok(command)
+    }
+    #[cfg(not(windows))]
+    {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(line);
+        Ok(command)
+    }
+}
+
+/// Makes command start in its own process group, so that processes it starts,
+/// f.e actual server started by `uv run` or `npm run`, could be stopped with it.
+pub(crate) fn new_group(command: &mut Command) {
+    #[cfg(unix)]
+    unix::new_group(command);
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Whether process or anything else in its group is still running.
+fn group_alive(child: &mut Child) -> bool {
+    // reap the process, exited but not reaped process would count as running
+    let exited = !matches!(child.try_wait(), Ok(None));
+    #[cfg(unix)]
+    return unix::signal_group(child, 0) || !exited;
+    #[cfg(not(unix))]
+    !exited
+}
+
+fn terminate_group(child: &mut Child) {
+    #[cfg(unix)]
+    unix::signal_group(child, libc::SIGTERM);
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    unix::signal_group(child, libc::SIGKILL);
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+/// Terminates process together with its group, killing them if they
+/// do not exit within timeout, and reaps the process.
+pub(crate) fn stop_group(child: &mut Child, kill_timeout: Duration) {
+    if group_alive(child) {
+        eprintln!("Terminating process group of {}", child.id());
+        terminate_group(child);
+        let started = Instant::now();
+        while group_alive(child) && started.elapsed() < kill_timeout {
+            thread::sleep(Duration::from_millis(50));
+        }
+        if group_alive(child) {
+            eprintln!(
+                "Process group of {} did not exit within {:?}, killing it",
+                child.id(),
+                kill_timeout
+            );
+            kill_group(child);
+        }
+    }
+
+    // reap the process to avoid leaving zombies behind
+    match child.wait() {
+        Ok(status) if status.success() => eprintln!("Process exited successfully"),
+        Ok(status) => eprintln!("Process exited with error: {:?}", status),
+        Err(e) => eprintln!("Failed to wait for process to exit: {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_plain_arguments_unquoted() {
+        for arg in ["run", "--port=8080", "src/main.py", "user@host:1,2"] {
+            assert_eq!(quote_sh(arg), arg);
+            assert_eq!(quote_cmd(arg).unwrap(), arg);
+        }
+    }
+
+    #[test]
+    fn quotes_arguments_for_sh() {
+        assert_eq!(quote_sh(""), "''");
+        assert_eq!(quote_sh("a b"), "'a b'");
+        assert_eq!(quote_sh("$HOME;x"), "'$HOME;x'");
+        assert_eq!(quote_sh("it's"), r"'it'\''s'");
+        assert_eq!(quote_sh("50%"), "'50%'");
+    }
+
+    #[test]
+    fn quotes_arguments_for_cmd() {
+        assert_eq!(quote_cmd("").unwrap(), r#""""#);
+        assert_eq!(quote_cmd("a b").unwrap(), r#""a b""#);
+        assert_eq!(quote_cmd("x&calc|y>z").unwrap(), r#""x&calc|y>z""#);
+        assert_eq!(
+            quote_cmd(r"C:\dir with space\").unwrap(),
+            r#""C:\dir with space\\""#
+        );
+    }
+
+    #[test]
+    fn rejects_arguments_cmd_cannot_take() {
+        for arg in [r#"a"&calc&"b"#, "%PATH%", "50%", "a\nb"] {
+            assert!(quote_cmd(arg).is_err(), "{:?} should be rejected", arg);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn passes_arguments_through_shell_unchanged() {
+        let args: Vec<String> = ["a b", "it's", "$HOME", "x;y", "\"q\"", ""]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let output = new_command("printf '[%s]'", &args, true)
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "[a b][it's][$HOME][x;y][\"q\"][]"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_program_directly_without_shell() {
+        let args = vec!["[%s]".to_string(), "a b".to_string()];
+        let output = new_command("printf", &args, false)
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "[a b]");
+    }
+}
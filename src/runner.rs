@@ -1,147 +1,626 @@
 use std::{
-    collections::HashMap,
-    env,
-    io::{stdin, BufRead, BufReader, Write},
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     process::{Child, Stdio},
-    sync::{Arc, Mutex},
-    thread::{self},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use crate::config::{self, Watch};
-use crossbeam_channel::{select, unbounded};
+use crate::{
+    build::BuildLog,
+    capabilities, cargo,
+    client::{Client, ClientSink},
+    config::{self, EnvConfig, Watch, WatchAction},
+    dotnet, env,
+    http::Url,
+    http_client,
+    java::{self, BuildTool},
+    jsonrpc::{self, Frame, Message, Outcome},
+    lists::{self, Lists},
+    process,
+    transport::{self, ServerInput, StdoutPolicy},
+    watch::{self, Plan},
+};
+use crossbeam_channel::{select, unbounded, Receiver, Sender};
 use eyre::Context;
-use notify_debouncer_full::{new_debouncer, notify::*, DebounceEventResult, RecommendedCache};
-use serde::Deserialize;
+use notify_debouncer_full::DebounceEventResult;
+use serde_json::{json, Value};
+
+/// How long synf waits for responses to requests it sends on its own.
+const OWN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often proxying thread checks whether server process is still alive.
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Server that ran for longer than this before crashing is not considered to be in a crash loop.
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(30);
+
+/// Longest delay before restarting crashed server.
+const MAX_CRASH_BACKOFF: Duration = Duration::from_secs(30);
 
 pub(crate) struct Runner {
-    debouncer: Option<notify_debouncer_full::Debouncer<RecommendedWatcher, RecommendedCache>>,
-    process: Option<std::process::Child>,
+    debouncer: Option<watch::Watcher>,
     path: PathBuf,
-    build_command: String,
-    build_args: Vec<String>,
+    /// Commands that build runs in sequence.
+    build_steps: Vec<BuildStep>,
+    build_log_file: Option<PathBuf>,
+    build_failure_lines: usize,
+    /// Environment of all commands, overridden by environments of build and run commands.
+    env: EnvConfig,
+    build_env: EnvConfig,
+    run_env: EnvConfig,
     run_command: String,
     run_args: Vec<String>,
-    language: config::Language,
+    run_dir: PathBuf,
+    run_shell: bool,
+    transport: ServerTransport,
+    ready_timeout: Duration,
+    initialize_timeout: Duration,
+    shutdown_grace: Duration,
+    kill_timeout: Duration,
+    strict_stdout: bool,
+    notify_stdout_pollution: bool,
+    restart_on_crash: bool,
+    max_crash_restarts: u32,
+    crash_backoff: Duration,
+    disconnect_grace: Duration,
+    build_gate: bool,
 
-    client_resource_subscriptions: Arc<Mutex<HashMap<String, String>>>,
+    /// Params of resource subscriptions by their URI.
+    client_resource_subscriptions: Arc<Mutex<HashMap<String, Value>>>,
     resend_resource_subscriptions: bool,
+    /// Whether subscriptions are tracked, to resend them or to notify client
+    /// only about updates of resources it has subscribed to.
+    track_resource_subscriptions: bool,
+    replay_in_flight_requests: bool,
+    /// Client requests interrupted by restart, to be sent to the next server.
+    replayed_requests: Arc<Mutex<Vec<String>>>,
     client_initialize_req: Arc<Mutex<Option<String>>>,
+    /// Whether client has received response to its initialize request.
+    client_initialized: Arc<Mutex<bool>>,
+    /// Lists of the last server, used to notify client only about changed ones.
+    server_lists: Arc<Mutex<Option<Lists>>>,
+    /// Capabilities that client received in the first initialize response.
+    client_capabilities: Arc<Mutex<Option<Value>>>,
     process_stopped_sender: Option<crossbeam_channel::Sender<()>>,
+    /// Incremented for every started server and at shutdown, to tell crashes of replaced servers apart.
+    generation: u64,
+    crash_sender: Sender<Crash>,
+    proxy_thread: Option<JoinHandle<()>>,
     stdin_receiver: Arc<Mutex<crossbeam_channel::Receiver<String>>>,
+    client: Arc<dyn ClientSink>,
 }
 
 impl Runner {
-    fn stop(process: &mut Child) {
-        if process.stdin.is_some() {
-            eprintln!("Closing running process stdin");
-            drop(process.stdin.take());
-            // give it some time to close before killing
-            thread::sleep(Duration::from_secs(2));
-        }
-
-        let should_try_killing: bool;
-        match process.try_wait() {
-            Ok(Some(status)) => {
-                if status.success() {
-                    eprintln!("Process exited successfully");
-                } else {
-                    eprintln!("Process exited with error: {:?}", status);
+    /// Runs build commands in sequence and reports whether they succeeded,
+    /// projects without build command are always considered built.
+    fn build(&self) -> bool {
+        if self.build_steps.is_empty() {
+            return true;
+        }
+
+        let mut log = BuildLog::new(
+            "[build]",
+            self.build_log_file.as_deref(),
+            self.build_failure_lines,
+        );
+        let started = Instant::now();
+        for (index, step) in self.build_steps.iter().enumerate() {
+            if !self.build_step(step, &mut log) {
+                for skipped in &self.build_steps[index + 1..] {
+                    eprintln!("Skipping build {}", skipped.label());
                 }
-                should_try_killing = false;
+                return false;
             }
-            Ok(None) => {
-                eprintln!("Process is still running");
-                should_try_killing = true;
+        }
+        eprintln!("Build succeeded in {:.1?}", started.elapsed());
+        true
+    }
+
+    /// Runs one of build commands, returns false if build cannot go on.
+    fn build_step(&self, step: &BuildStep, log: &mut BuildLog) -> bool {
+        let label = step.label();
+        eprintln!(
+            "Running build {}: {:?} {:?}",
+            label, step.command, step.args
+        );
+        let mut command = match process::new_command(&step.command, &step.args, step.shell) {
+            Ok(command) => command,
+            Err(e) => {
+                let summary = format!("Error running build {}: {:#}", label, e);
+                eprintln!("{}", summary);
+                self.report_build_failure(&summary, log);
+                return false;
+            }
+        };
+        command.current_dir(&step.dir);
+        match env::resolve(&self.path, &[&self.env, &self.build_env, &step.env]) {
+            Ok(vars) => {
+                command.envs(vars);
             }
             Err(e) => {
-                eprintln!("Failed to check if process is closed: {:?}", e);
-                should_try_killing = true;
+                let summary = format!("Error resolving environment of build {}: {:#}", label, e);
+                eprintln!("{}", summary);
+                self.report_build_failure(&summary, log);
+                return false;
             }
         }
 
-        if should_try_killing {
-            match process.kill() {
-                Ok(_) => {
-                    eprintln!("Killed running process");
-                }
-                Err(e) => {
-                    eprintln!("Failed to kill running process: {:?}", e);
+        let started = Instant::now();
+        let summary = match log.run(&mut command) {
+            Ok(status) if status.success() => {
+                eprintln!("Build {} succeeded in {:.1?}", label, started.elapsed());
+                return true;
+            }
+            Ok(status) => format!(
+                "Build {} failed with {} after {:.1?}",
+                label,
+                status,
+                started.elapsed()
+            ),
+            Err(e) => format!("Error running build {}: {}", label, e),
+        };
+        if step.continue_on_error {
+            eprintln!("{}, continuing as it has continue_on_error", summary);
+            return true;
+        }
+        eprintln!("{}", summary);
+        self.report_build_failure(&summary, log);
+        false
+    }
+
+    /// Lets person using the client know why server was not reloaded.
+    fn report_build_failure(&self, summary: &str, log: &BuildLog) {
+        if self.build_failure_lines == 0 {
+            return;
+        }
+        let mut message = summary.to_string();
+        for line in log.tail() {
+            message.push('\n');
+            message.push_str(&line);
+        }
+        self.notify_client("error", &message);
+    }
+
+    /// Sends log message to client, unless it has not initialized yet.
+    fn notify_client(&self, level: &str, message: &str) {
+        // client must not receive anything before it has initialized
+        if self.client_initialize_req.lock().unwrap().is_none() {
+            return;
+        }
+        self.client.send(&jsonrpc::log_notification(level, message));
+    }
+
+    fn spawn(&self) -> eyre::Result<ServerProcess> {
+        eprintln!(
+            "Running run command: {:?} {:?}",
+            self.run_command, self.run_args
+        );
+        let mut command = process::new_command(&self.run_command, &self.run_args, self.run_shell)?;
+        command
+            .stderr(Stdio::inherit())
+            .current_dir(&self.run_dir)
+            .envs(env::resolve(&self.path, &[&self.env, &self.run_env])?);
+        process::new_group(&mut command);
+        match self.transport {
+            ServerTransport::Stdio => {
+                command.stdin(Stdio::piped()).stdout(Stdio::piped());
+            }
+            ServerTransport::Http(_) | ServerTransport::Sse(_) => {
+                // stdout of http server is not a protocol stream, so it must not reach client
+                command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::from(std::io::stderr()));
+            }
+        }
+        let mut child = command.spawn().context("failed to spawn run command")?;
+
+        let connection = match &self.transport {
+            ServerTransport::Stdio => {
+                let policy = StdoutPolicy {
+                    process: format!("server process {:?} (pid {})", self.run_command, child.id()),
+                    strict: self.strict_stdout,
+                    notify_client: self.notify_stdout_pollution,
+                };
+                transport::stdio(
+                    child.stdin.take().unwrap(),
+                    child.stdout.take().unwrap(),
+                    policy,
+                )
+            }
+            ServerTransport::Http(url) | ServerTransport::Sse(url) => {
+                let connection = self.wait_ready(&mut child, url).and_then(|_| {
+                    if let ServerTransport::Sse(url) = &self.transport {
+                        http_client::sse(url.clone())
+                    } else {
+                        Ok(http_client::streamable(url.clone()))
+                    }
+                });
+                match connection {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        process::stop_group(&mut child, self.kill_timeout);
+                        return Err(e);
+                    }
                 }
             }
+        };
+
+        Ok(ServerProcess {
+            child,
+            input: connection.input,
+            output: connection.output,
+            broken: connection.broken,
+            own_requests: OwnRequests::default(),
+            in_flight: InFlight::default(),
+            early_output: Vec::new(),
+            initialize_timeout: self.initialize_timeout,
+            shutdown_grace: self.shutdown_grace,
+            kill_timeout: self.kill_timeout,
+            initialize_response: None,
+            capabilities: None,
+            started: Instant::now(),
+        })
+    }
+
+    /// Waits until server started by run command accepts connections.
+    fn wait_ready(&self, child: &mut Child, url: &Url) -> eyre::Result<()> {
+        eprintln!(
+            "Waiting for server to accept connections at {}",
+            url.address()
+        );
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Err(eyre::eyre!(
+                    "process exited with {} before accepting connections",
+                    status
+                ));
+            }
+            if url.accepts_connections(Duration::from_millis(200)) {
+                eprintln!("Server accepts connections");
+                return Ok(());
+            }
+            if started.elapsed() > self.ready_timeout {
+                return Err(eyre::eyre!(
+                    "server did not accept connections at {} within {:?}",
+                    url.address(),
+                    self.ready_timeout
+                ));
+            }
+            thread::sleep(Duration::from_millis(100));
         }
     }
 
+    /// Whether there is a server process being proxied at the moment.
+    fn is_running(&self) -> bool {
+        // proxying thread exits on its own once server process crashes
+        self.process_stopped_sender.is_some()
+            && self
+                .proxy_thread
+                .as_ref()
+                .is_some_and(|proxy_thread| !proxy_thread.is_finished())
+    }
+
     pub(crate) fn trigger(&mut self) {
-        let build_command = self.build_command.clone();
-        let build_args = self.build_args.clone();
-        let path = self.path.clone();
-        let no_build = self.build_command == "";
-
-        // if windows - wrap in cmd shell, otherwise just run
-        if !no_build {
-            eprintln!(
-                "Running build command: {:?} {:?}",
-                build_command, build_args
-            );
-            let status = std::process::Command::new(build_command)
-                .args(build_args)
-                .current_dir(path)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped()) // todo: handle stderr to provide logs
-                .status();
-            match status {
-                Ok(status) => {
-                    if status.success() {
-                        eprintln!("Build succeeded");
-                    } else {
-                        eprintln!("Build failed");
+        self.reload(Reload::Full);
+    }
+
+    /// Does what is planned for changes in watched paths.
+    fn apply(&mut self, plan: Plan) {
+        match plan {
+            Plan::Rebuild => {
+                eprintln!("Debouncer triggers reload");
+                self.trigger();
+            }
+            Plan::Restart => {
+                eprintln!("Debouncer triggers restart");
+                self.reload(Reload::RestartOnly);
+            }
+            Plan::Run(commands) => {
+                for command in commands {
+                    let program = command.command.unwrap_or_default();
+                    let args = command.args.unwrap_or_default();
+                    eprintln!("Running watch command: {:?} {:?}", program, args);
+                    let mut log = BuildLog::new("[watch]", None, 0);
+                    let shell = command.shell.unwrap_or(false);
+                    let mut process = match process::new_command(&program, &args, shell) {
+                        Ok(process) => process,
+                        Err(e) => {
+                            eprintln!("Error running watch command: {:#}", e);
+                            continue;
+                        }
+                    };
+                    process.current_dir(Self::command_dir(&self.path, command.cwd.as_deref()));
+                    match env::resolve(&self.path, &[&self.env]) {
+                        Ok(vars) => {
+                            process.envs(vars);
+                        }
+                        Err(e) => {
+                            eprintln!("Error resolving watch command environment: {:#}", e);
+                            continue;
+                        }
+                    }
+                    match log.run(&mut process) {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => eprintln!("Watch command failed with {}", status),
+                        Err(e) => eprintln!("Error running watch command: {:?}", e),
                     }
                 }
-                Err(e) => {
-                    eprintln!("Error running build command: {:?}", e);
+            }
+            Plan::NotifyResources(uris) => {
+                // client must not receive anything before it has initialized
+                if self.client_initialize_req.lock().unwrap().is_none() {
+                    return;
+                }
+                let subscriptions = self.client_resource_subscriptions.lock().unwrap();
+                for uri in uris {
+                    if !subscriptions.contains_key(&uri) {
+                        continue;
+                    }
+                    eprintln!("Notifying client that resource {} was updated", uri);
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/resources/updated",
+                        "params": {"uri": uri},
+                    });
+                    self.client.send(&notification.to_string());
                 }
             }
         }
+    }
+
+    /// Rebuilds and/or restarts server, returns false if anything has failed.
+    pub(crate) fn reload(&mut self, reload: Reload) -> bool {
+        if reload == Reload::BuildOnly {
+            return self.build();
+        }
 
-        // Use the configured run command and args from the instance
-        // instead of getting the default values again
-        let run_command = self.run_command.clone();
-        let run_args = self.run_args.clone();
+        // with build gate, previous server is only replaced by a new one
+        // that was built, started and initialized successfully
+        let gated = self.build_gate && self.is_running();
 
-        if let Some(stopped_tx) = &mut self.process_stopped_sender {
-            eprintln!("Sending stop to tx");
-            stopped_tx.send(()).unwrap();
+        let built = reload == Reload::RestartOnly || self.build();
+        if !built && gated {
+            eprintln!("Build failed, keeping previous server running until next successful build");
+            return false;
         }
 
-        if let Some(process) = &mut self.process {
-            Self::stop(process);
+        // http servers would compete for the same port, so previous one
+        // has to be stopped before new one is started
+        let replace_after_start = gated && self.transport == ServerTransport::Stdio;
+
+        if !replace_after_start {
+            self.stop_running();
         }
 
-        eprintln!("Running run command: {:?} {:?}", run_command, run_args);
-        let process = std::process::Command::new(run_command)
-            .args(run_args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .current_dir(self.path.clone())
-            .spawn();
+        let mut server = match self.spawn() {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Error running run command: {:#}", e);
+                if replace_after_start {
+                    eprintln!("Keeping previous server running");
+                }
+                return false;
+            }
+        };
+        eprintln!("Command has started");
 
-        match process {
-            Ok(process) => {
-                self.process = Some(process);
+        if replace_after_start {
+            // initialize new server while previous one is still serving client,
+            // unless client has not initialized yet and there is nothing to repeat
+            let init_req = self.client_initialize_req.lock().unwrap().clone();
+            if let Some(init_req) = init_req {
+                if let Err(e) = server.initialize(&init_req, &crossbeam_channel::never()) {
+                    eprintln!("New server failed to initialize: {:#}", e);
+                    eprintln!("Keeping previous server running");
+                    server.stop();
+                    return false;
+                }
             }
-            Err(e) => {
-                eprintln!("Error running run command: {:?}", e);
+            self.stop_running();
+        }
+
+        self.run(server);
+        built
+    }
+
+    /// Stops watching and currently running server process and waits until
+    /// it has exited, as nothing would stop it after synf exits.
+    pub(crate) fn shutdown(&mut self) {
+        self.debouncer = None;
+        // crashes waiting for restart are ignored as if server was replaced
+        self.generation += 1;
+        self.stop_running();
+        if let Some(proxy_thread) = self.proxy_thread.take() {
+            let _ = proxy_thread.join();
+        }
+    }
+
+    /// Shuts down once client has disconnected, giving server up to disconnect
+    /// grace period to respond to requests that client has sent before that.
+    pub(crate) fn disconnect(&mut self) {
+        let started = Instant::now();
+        while self.is_running() && started.elapsed() < self.disconnect_grace {
+            thread::sleep(Duration::from_millis(50));
+        }
+        self.shutdown();
+    }
+
+    /// Signals proxying thread to stop currently running server process.
+    fn stop_running(&mut self) {
+        if let Some(stopped_tx) = self.process_stopped_sender.take() {
+            eprintln!("Sending stop to tx");
+            // thread might have already exited if process died on its own
+            let _ = stopped_tx.send(());
+        }
+        if self.transport != ServerTransport::Stdio {
+            // wait until port is released by previous server
+            if let Some(proxy_thread) = self.proxy_thread.take() {
+                let _ = proxy_thread.join();
             }
         }
-        eprintln!("Command has started");
-        self.run().unwrap();
     }
 
-    pub(crate) fn get_run_command(language: &config::Language) -> (String, Vec<String>) {
+    /// Restarts servers that crashed on their own with exponential backoff,
+    /// until they crash too many times in a row shortly after start.
+    fn restart_crashed(crashes: Receiver<Crash>, runner: Arc<Mutex<Runner>>) {
+        // crashes in a row of servers restarted here
+        let mut restarts = 0;
+        let mut restarted_generation = None;
+        // failed restart, which is retried like a crash of restarted server
+        let mut failed = None;
+        loop {
+            let crash = match failed.take() {
+                Some(crash) => crash,
+                None => match crashes.recv() {
+                    Ok(crash) => crash,
+                    Err(_) => return,
+                },
+            };
+            let delay = {
+                let runner = runner.lock().unwrap();
+                if crash.generation != runner.generation {
+                    // server was already replaced after a change
+                    continue;
+                }
+                if restarted_generation != Some(crash.generation)
+                    || crash.uptime >= CRASH_LOOP_WINDOW
+                {
+                    restarts = 0;
+                }
+
+                let delay = runner
+                    .crash_backoff
+                    .saturating_mul(2u32.saturating_pow(restarts))
+                    .min(MAX_CRASH_BACKOFF);
+                let message = if !runner.restart_on_crash {
+                    format!(
+                        "Server process {}, it would be restarted after next change",
+                        crash.reason
+                    )
+                } else if restarts >= runner.max_crash_restarts {
+                    format!(
+                        "Server process {} after {} restarts in a row, not restarting it until next change",
+                        crash.reason, restarts
+                    )
+                } else {
+                    format!(
+                        "Server process {}, restarting it in {:?}",
+                        crash.reason, delay
+                    )
+                };
+                eprintln!("{}", message);
+                runner.notify_client("error", &message);
+                if !runner.restart_on_crash || restarts >= runner.max_crash_restarts {
+                    continue;
+                }
+                delay
+            };
+
+            thread::sleep(delay);
+            let mut runner = runner.lock().unwrap();
+            if crash.generation != runner.generation {
+                continue;
+            }
+            restarts += 1;
+            eprintln!("Restarting crashed server, attempt {}", restarts);
+            if !runner.reload(Reload::RestartOnly) {
+                // server that has not started would never report its exit
+                failed = Some(Crash {
+                    generation: runner.generation,
+                    reason: "failed to restart".to_string(),
+                    uptime: Duration::ZERO,
+                });
+            }
+            restarted_generation = Some(runner.generation);
+        }
+    }
+
+    /// Directory to run command in, which is project unless cwd relative to it is given.
+    fn command_dir(project: &Path, cwd: Option<&str>) -> PathBuf {
+        match cwd {
+            Some(cwd) => project.join(cwd),
+            None => project.to_path_buf(),
+        }
+    }
+
+    /// Commands that build runs, which are either configured steps
+    /// or the only build command, if there is one.
+    fn build_steps(path: &Path, cfg: &config::Config) -> eyre::Result<Vec<BuildStep>> {
+        let (default_command, default_args) = Self::get_build_command(&cfg.language, path);
+        let build = cfg.build.as_ref();
+        let cwd = build.and_then(|build| build.command.cwd.as_deref());
+        let shell = build.and_then(|build| build.command.shell);
+
+        let Some(steps) = build.and_then(|build| build.steps.as_ref()) else {
+            let command = build
+                .and_then(|build| build.command.command.clone())
+                .unwrap_or(default_command);
+            if command.is_empty() {
+                return Ok(Vec::new());
+            }
+            return Ok(vec![BuildStep {
+                name: None,
+                command,
+                args: build
+                    .and_then(|build| build.command.args.clone())
+                    .unwrap_or(default_args),
+                dir: Self::command_dir(path, cwd),
+                shell: shell.unwrap_or(false),
+                env: EnvConfig::default(),
+                continue_on_error: false,
+            }]);
+        };
+
+        if build
+            .is_some_and(|build| build.command.command.is_some() || build.command.args.is_some())
+        {
+            return Err(eyre::eyre!(
+                "[build] can have either command and args or steps, but not both"
+            ));
+        }
+        steps
+            .iter()
+            .map(|step| {
+                let Some(command) = step.command.command.clone() else {
+                    return Err(eyre::eyre!(
+                        "command is required for build step {:?}",
+                        step.name
+                    ));
+                };
+                Ok(BuildStep {
+                    name: Some(step.name.clone()),
+                    command,
+                    args: step.command.args.clone().unwrap_or_default(),
+                    // steps inherit cwd and shell of [build], if they do not set them
+                    dir: Self::command_dir(path, step.command.cwd.as_deref().or(cwd)),
+                    shell: step.command.shell.or(shell).unwrap_or(false),
+                    env: step.env.clone(),
+                    continue_on_error: step.continue_on_error.unwrap_or(false),
+                })
+            })
+            .collect()
+    }
+
+    /// Script that runs server of scripting language, which is the first of the usual names
+    /// that exists in project, or the first name if none of them does.
+    fn entry_script(path: &Path, names: &[&str]) -> String {
+        names
+            .iter()
+            .find(|name| path.join(name).exists())
+            .unwrap_or(&names[0])
+            .to_string()
+    }
+
+    pub(crate) fn get_run_command(
+        language: &config::Language,
+        path: &Path,
+    ) -> (String, Vec<String>) {
         match language {
             config::Language::Typescript => {
                 ("node".to_string(), vec!["build/index.js".to_string()])
@@ -152,10 +631,82 @@ impl Runner {
                 vec!["run".to_string(), "main.go".to_string()],
             ),
             config::Language::Kotlin => ("./gradlew".to_string(), vec!["run".to_string()]),
+            config::Language::Rust => match cargo::binary(path) {
+                Ok(binary) => (binary.to_string_lossy().into_owned(), vec![]),
+                Err(e) => {
+                    eprintln!("Warning: {:#}, using cargo run instead", e);
+                    ("cargo".to_string(), vec!["run".to_string()])
+                }
+            },
+            // running build output directly avoids starting gradle or maven on every restart
+            config::Language::Java => match java::build_tool(path) {
+                BuildTool::Maven => match java::maven_jar(path) {
+                    Ok(jar) => (
+                        "java".to_string(),
+                        vec!["-jar".to_string(), jar.to_string_lossy().replace('\\', "/")],
+                    ),
+                    Err(e) => {
+                        eprintln!("Warning: {:#}, using mvn exec:java instead", e);
+                        (
+                            "mvn".to_string(),
+                            vec!["-q".to_string(), "exec:java".to_string()],
+                        )
+                    }
+                },
+                BuildTool::Gradle => (
+                    Path::new(".")
+                        .join(java::gradle_script(path))
+                        .to_string_lossy()
+                        .into_owned(),
+                    vec![],
+                ),
+            },
+            config::Language::Deno => (
+                "deno".to_string(),
+                vec!["run".to_string(), "-A".to_string(), "main.ts".to_string()],
+            ),
+            config::Language::Bun => (
+                "bun".to_string(),
+                vec!["run".to_string(), "src/index.ts".to_string()],
+            ),
+            // dll starts faster than dotnet run, which checks whether to build first
+            config::Language::Csharp => match dotnet::dll(path) {
+                Ok(dll) => (
+                    "dotnet".to_string(),
+                    vec![dll.to_string_lossy().replace('\\', "/")],
+                ),
+                Err(e) => {
+                    eprintln!("Warning: {:#}, using dotnet run instead", e);
+                    ("dotnet".to_string(), vec!["run".to_string()])
+                }
+            },
+            config::Language::Ruby => (
+                "bundle".to_string(),
+                vec![
+                    "exec".to_string(),
+                    "ruby".to_string(),
+                    Self::entry_script(path, &["server.rb", "main.rb", "app.rb"]),
+                ],
+            ),
+            config::Language::Php => (
+                "php".to_string(),
+                vec![Self::entry_script(
+                    path,
+                    &["server.php", "main.php", "index.php"],
+                )],
+            ),
+            // without --no-halt mix would exit right after starting application
+            config::Language::Elixir => (
+                "mix".to_string(),
+                vec!["run".to_string(), "--no-halt".to_string()],
+            ),
         }
     }
 
-    pub(crate) fn get_build_command(language: &config::Language) -> (String, Vec<String>) {
+    pub(crate) fn get_build_command(
+        language: &config::Language,
+        path: &Path,
+    ) -> (String, Vec<String>) {
         match language {
             config::Language::Typescript => (
                 "npm".to_string(),
@@ -164,10 +715,26 @@ impl Runner {
             config::Language::Python => ("".to_string(), vec![]),
             config::Language::Golang => ("".to_string(), vec![]),
             config::Language::Kotlin => ("".to_string(), vec![]),
+            config::Language::Rust => ("cargo".to_string(), vec!["build".to_string()]),
+            config::Language::Java => match java::build_tool(path) {
+                BuildTool::Maven => (
+                    "mvn".to_string(),
+                    vec!["-q".to_string(), "package".to_string()],
+                ),
+                BuildTool::Gradle => ("./gradlew".to_string(), vec!["installDist".to_string()]),
+            },
+            // both run typescript without compiling it first
+            config::Language::Deno => ("".to_string(), vec![]),
+            config::Language::Bun => ("".to_string(), vec![]),
+            config::Language::Csharp => ("dotnet".to_string(), vec!["build".to_string()]),
+            config::Language::Ruby => ("".to_string(), vec![]),
+            config::Language::Php => ("".to_string(), vec![]),
+            // compiling in run would print compiler output to stdout, which is for messages
+            config::Language::Elixir => ("mix".to_string(), vec!["compile".to_string()]),
         }
     }
 
-    pub(crate) fn get_default_watch_paths(language: &config::Language) -> Vec<String> {
+    pub(crate) fn get_default_watch_paths(language: &config::Language, path: &Path) -> Vec<String> {
         match language {
             config::Language::Typescript => vec!["src".to_string(), "package.json".to_string()],
             config::Language::Python => vec!["src".to_string(), "pyproject.toml".to_string()],
@@ -177,61 +744,244 @@ impl Runner {
                 "build.gradle.kts".to_string(),
                 "gradle.properties".to_string(),
             ],
+            config::Language::Rust => cargo::watch_paths(path).unwrap_or_else(|e| {
+                eprintln!("Warning: {:#}", e);
+                vec![
+                    "src".to_string(),
+                    "Cargo.toml".to_string(),
+                    "build.rs".to_string(),
+                ]
+            }),
+            config::Language::Java => {
+                let build_files: &[&str] = match java::build_tool(path) {
+                    BuildTool::Maven => &["pom.xml"],
+                    BuildTool::Gradle => &[
+                        "build.gradle",
+                        "build.gradle.kts",
+                        "settings.gradle",
+                        "settings.gradle.kts",
+                    ],
+                };
+                std::iter::once("src/main")
+                    .chain(build_files.iter().copied())
+                    .map(str::to_string)
+                    .collect()
+            }
+            config::Language::Deno => vec![
+                "main.ts".to_string(),
+                "src".to_string(),
+                "deno.json".to_string(),
+                "deno.jsonc".to_string(),
+            ],
+            config::Language::Bun => vec![
+                "src".to_string(),
+                "package.json".to_string(),
+                "bun.lockb".to_string(),
+                "bun.lock".to_string(),
+            ],
+            config::Language::Csharp => dotnet::watch_paths(path),
+            config::Language::Ruby => vec![
+                Self::entry_script(path, &["server.rb", "main.rb", "app.rb"]),
+                "lib".to_string(),
+                "Gemfile".to_string(),
+                "Gemfile.lock".to_string(),
+            ],
+            config::Language::Php => vec![
+                Self::entry_script(path, &["server.php", "main.php", "index.php"]),
+                "src".to_string(),
+                "composer.json".to_string(),
+                "composer.lock".to_string(),
+            ],
+            config::Language::Elixir => vec![
+                "lib".to_string(),
+                "config".to_string(),
+                "mix.exs".to_string(),
+                "mix.lock".to_string(),
+            ],
         }
     }
 
-    pub(crate) fn new(path: PathBuf, cfg: config::Config) -> eyre::Result<Arc<Mutex<Self>>> {
-        let (build_command, build_args) = Self::get_build_command(&cfg.language);
-
-        let (build_command, build_args) = if let Some(custom_build_config) = cfg.build {
-            let command = custom_build_config.command.unwrap_or(build_command);
-            let args = custom_build_config.args.unwrap_or(build_args);
-            (command, args)
-        } else {
-            (build_command, build_args)
-        };
+    /// Include and exclude patterns that are used when [watch] does not set them,
+    /// empty include means all paths.
+    pub(crate) fn get_default_watch_filter(
+        language: &config::Language,
+    ) -> (Vec<String>, Vec<String>) {
+        match language {
+            // projects are watched as whole folders, which have build output in bin and obj
+            config::Language::Csharp => (
+                vec![
+                    "**/*.cs".to_string(),
+                    "**/*.csproj".to_string(),
+                    "**/appsettings*.json".to_string(),
+                    "*.sln".to_string(),
+                ],
+                vec!["**/bin/**".to_string(), "**/obj/**".to_string()],
+            ),
+            _ => (vec![], vec![]),
+        }
+    }
 
-        let (run_command, run_args) = Self::get_run_command(&cfg.language);
+    pub(crate) fn new(
+        path: PathBuf,
+        cfg: config::Config,
+        client: Client,
+    ) -> eyre::Result<Arc<Mutex<Self>>> {
+        let build_steps = Self::build_steps(&path, &cfg)?;
 
-        let (run_command, run_args) = if let Some(custom_run_config) = cfg.run {
-            let command = custom_run_config.command.unwrap_or(run_command);
-            let args = custom_run_config.args.unwrap_or(run_args);
-            (command, args)
-        } else {
-            (run_command, run_args)
+        let custom_run_config = cfg.run.as_ref().map(|run| &run.command);
+        let (run_command, run_args) = match custom_run_config {
+            Some(config::CommandConfig {
+                command: Some(command),
+                args: Some(args),
+                ..
+            }) => (command.clone(), args.clone()),
+            // default might take time to find, f.e binary of rust project
+            _ => {
+                let (run_command, run_args) = Self::get_run_command(&cfg.language, &path);
+                (
+                    custom_run_config
+                        .and_then(|run| run.command.clone())
+                        .unwrap_or(run_command),
+                    custom_run_config
+                        .and_then(|run| run.args.clone())
+                        .unwrap_or(run_args),
+                )
+            }
         };
 
-        let (sender, receiver) = unbounded::<String>();
-
-        thread::spawn(move || {
-            for line in stdin().lines() {
-                match line {
-                    Ok(line) => {
-                        sender.send(line).unwrap();
-                    }
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        return;
-                    }
+        let transport = match cfg.run.as_ref().and_then(|run| run.transport.as_ref()) {
+            None | Some(config::Transport::Stdio) => ServerTransport::Stdio,
+            Some(transport) => {
+                let Some(url) = cfg.run.as_ref().and_then(|run| run.url.as_ref()) else {
+                    return Err(eyre::eyre!(
+                        "url is required in [run] for {:?} transport",
+                        transport
+                    ));
+                };
+                let url = Url::parse(url).context("invalid url in [run]")?;
+                if *transport == config::Transport::Sse {
+                    ServerTransport::Sse(url)
+                } else {
+                    ServerTransport::Http(url)
                 }
             }
-        });
+        };
+        let ready_timeout = Duration::from_millis(
+            cfg.run
+                .as_ref()
+                .and_then(|run| run.ready_timeout_ms)
+                .unwrap_or(30_000),
+        );
+
+        let initialize_timeout = Duration::from_millis(
+            cfg.run
+                .as_ref()
+                .and_then(|run| run.initialize_timeout_ms)
+                .unwrap_or(30_000),
+        );
+
+        let (crash_sender, crashes) = unbounded::<Crash>();
 
         let mut therunner = Runner {
             debouncer: None,
-            process: None,
-            build_args,
-            build_command,
+            build_steps,
+            build_log_file: cfg
+                .build
+                .as_ref()
+                .and_then(|build| build.log_file.as_ref())
+                .map(|log_file| path.join(log_file)),
+            build_failure_lines: cfg
+                .build
+                .as_ref()
+                .and_then(|build| build.failure_lines)
+                .unwrap_or(20),
+            env: cfg.env.clone(),
+            build_env: cfg
+                .build
+                .as_ref()
+                .map(|build| build.env.clone())
+                .unwrap_or_default(),
+            run_env: cfg
+                .run
+                .as_ref()
+                .map(|run| run.env.clone())
+                .unwrap_or_default(),
             run_command,
             run_args,
+            run_dir: Self::command_dir(
+                &path,
+                cfg.run.as_ref().and_then(|run| run.command.cwd.as_deref()),
+            ),
+            run_shell: cfg
+                .run
+                .as_ref()
+                .and_then(|run| run.command.shell)
+                .unwrap_or(false),
+            transport,
+            ready_timeout,
+            initialize_timeout,
+            shutdown_grace: Duration::from_millis(
+                cfg.run
+                    .as_ref()
+                    .and_then(|run| run.shutdown_grace_ms)
+                    .unwrap_or(2000),
+            ),
+            kill_timeout: Duration::from_millis(
+                cfg.run
+                    .as_ref()
+                    .and_then(|run| run.kill_timeout_ms)
+                    .unwrap_or(5000),
+            ),
+            strict_stdout: cfg
+                .run
+                .as_ref()
+                .and_then(|run| run.strict_stdout)
+                .unwrap_or(false),
+            notify_stdout_pollution: cfg
+                .run
+                .as_ref()
+                .and_then(|run| run.notify_stdout_pollution)
+                .unwrap_or(false),
+            restart_on_crash: cfg
+                .run
+                .as_ref()
+                .and_then(|run| run.restart_on_crash)
+                .unwrap_or(true),
+            max_crash_restarts: cfg
+                .run
+                .as_ref()
+                .and_then(|run| run.max_crash_restarts)
+                .unwrap_or(5),
+            crash_backoff: Duration::from_millis(
+                cfg.run
+                    .as_ref()
+                    .and_then(|run| run.crash_backoff_ms)
+                    .unwrap_or(500),
+            ),
+            disconnect_grace: Duration::from_millis(cfg.disconnect_grace_ms.unwrap_or(2000)),
             path: path.clone(),
-            language: cfg.language.clone(),
+            build_gate: cfg.build_gate.unwrap_or(false),
 
             client_resource_subscriptions: Arc::new(Mutex::new(HashMap::new())),
             resend_resource_subscriptions: cfg.resend_resource_subscriptions.unwrap_or(false),
+            track_resource_subscriptions: cfg.resend_resource_subscriptions.unwrap_or(false)
+                || cfg
+                    .watch
+                    .iter()
+                    .flat_map(|watch| watch.rules.iter().flatten())
+                    .any(|rule| rule.action == WatchAction::NotifyResources),
+            replay_in_flight_requests: cfg.replay_in_flight_requests.unwrap_or(false),
+            replayed_requests: Arc::new(Mutex::new(Vec::new())),
             client_initialize_req: Arc::new(Mutex::new(None)),
+            client_initialized: Arc::new(Mutex::new(false)),
+            server_lists: Arc::new(Mutex::new(None)),
+            client_capabilities: Arc::new(Mutex::new(None)),
             process_stopped_sender: None,
-            stdin_receiver: Arc::new(Mutex::new(receiver)),
+            generation: 0,
+            crash_sender,
+            proxy_thread: None,
+            stdin_receiver: Arc::new(Mutex::new(client.messages)),
+            client: client.sink,
         };
 
         therunner.trigger();
@@ -239,36 +989,83 @@ impl Runner {
         let runner_arc = Arc::new(Mutex::new(therunner));
         let runner_arc_clone = runner_arc.clone();
 
-        let mut debouncer = new_debouncer(
-            Duration::from_secs(2),
-            None,
-            move |result: DebounceEventResult| match result {
-                Ok(events) => {
-                    events.iter().for_each(|event| {
-                    eprintln!("{event:?}");
-                });
+        let crashed_runner = runner_arc.clone();
+        thread::spawn(move || Self::restart_crashed(crashes, crashed_runner));
 
-                eprintln!("Debouncer triggers reload");
-                runner_arc_clone.lock().unwrap().trigger();
-            },
-                Err(errors) => errors.iter().for_each(|error| eprintln!("{error:?}")),
-            },
+        let (mut default_include, default_exclude) = Self::get_default_watch_filter(&cfg.language);
+        // default include only covers files of the language in default paths,
+        // so it would filter out changes in extra paths and paths of rules,
+        // while default exclude of build output is kept to not reload in a loop
+        if cfg
+            .watch
+            .as_ref()
+            .is_some_and(|watch| watch.extra_paths.is_some() || watch.rules.is_some())
+        {
+            default_include.clear();
+        }
+        let mut filter = watch::Filter::new(
+            &path,
+            cfg.watch.as_ref(),
+            &default_include,
+            &default_exclude,
+        )?;
+        let mut rules = watch::Rules::new(&path, cfg.watch.as_ref())?;
+        // changed variables only take effect once commands are run again
+        let env_files = [
+            (Some(&cfg.env), WatchAction::Rebuild),
+            (
+                cfg.build.as_ref().map(|build| &build.env),
+                WatchAction::Rebuild,
+            ),
+            (cfg.run.as_ref().map(|run| &run.env), WatchAction::Restart),
+        ]
+        .into_iter()
+        .chain(
+            cfg.build
+                .iter()
+                .flat_map(|build| build.steps.iter().flatten())
+                .map(|step| (Some(&step.env), WatchAction::Rebuild)),
         )
-        .context("failed to create debouncer to watch path")?;
+        .filter_map(|(env, action)| Some((PathBuf::from(env?.env_file.as_ref()?), action)))
+        .collect::<Vec<_>>();
+        for (env_file, action) in &env_files {
+            filter.keep(env_file);
+            rules.path(env_file, *action);
+        }
+        let debounce = cfg.watch.as_ref().and_then(|watch| watch.debounce_ms);
+        let (events_sender, events) = unbounded::<DebounceEventResult>();
+        let mut debouncer = watch::Watcher::new(
+            cfg.watch.as_ref(),
+            Duration::from_millis(debounce.unwrap_or(2000)),
+            events_sender,
+        );
+        thread::spawn(move || {
+            watch::apply_on_changes(events, filter, rules, |plan| {
+                runner_arc_clone.lock().unwrap().apply(plan);
+            })
+        });
 
-        let default_watch_paths = match &cfg.watch {
+        let (default_watch_paths, configured) = match &cfg.watch {
             Some(Watch {
                 default_paths: Some(configured_default_paths),
                 ..
-            }) => configured_default_paths.clone(),
-            _ => Self::get_default_watch_paths(&cfg.language),
+            }) => (configured_default_paths.clone(), true),
+            _ => (Self::get_default_watch_paths(&cfg.language, &path), false),
         };
 
         for watch_path in default_watch_paths {
             let watch_path = path.join(watch_path);
+            if !configured && !watch_path.exists() {
+                // not every project has all the files that are usual for its language
+                eprintln!(
+                    "Not watching default path {:?} as it does not exist",
+                    watch_path
+                );
+                continue;
+            }
             eprintln!("Watching default path {:?}", watch_path);
             debouncer
-                .watch(watch_path.clone(), RecursiveMode::Recursive)
+                .watch(&watch_path)
                 .with_context(|| format!("failed to watch default path {:?}", watch_path))?;
         }
 
@@ -277,7 +1074,7 @@ impl Runner {
                 eprintln!("Watching extra path {:?}", watch_path);
                 let watch_path = path.join(watch_path);
                 debouncer
-                    .watch(watch_path.clone(), RecursiveMode::Recursive)
+                    .watch(&watch_path)
                     .with_context(|| format!("failed to watch extra path {:?}", watch_path))?;
             }
         } else {
@@ -286,201 +1083,252 @@ impl Runner {
                 eprintln!("Warning: no extra watch paths provided for golang, only watching go.mod, you probably want to add more paths, like internal/, cmd/, etc.");
             }
         }
+        for (env_file, _) in env_files {
+            let env_file = path.join(env_file);
+            eprintln!("Watching env file {:?}", env_file);
+            debouncer
+                .watch(&env_file)
+                .with_context(|| format!("failed to watch env file {:?}", env_file))?;
+        }
         runner_arc.lock().unwrap().debouncer = Some(debouncer);
         Ok(runner_arc)
     }
 
-    pub(crate) fn run(&mut self) -> eyre::Result<()> {
-        // firstly wait until we have a running process
-        while self.process.is_none() {
-            // TODO: use channels to wait efficiently
-            eprintln!("Waiting for process to start");
-            thread::sleep(Duration::from_secs(1));
-        }
-
-        let mut process = self.process.take().unwrap();
+    pub(crate) fn run(&mut self, server: ServerProcess) {
+        let mut server = server;
         let init_req = self.client_initialize_req.clone();
-
-        let mut process_input = process.stdin.take().unwrap();
-        let process_out = process.stdout.take().unwrap();
+        let client_initialized = self.client_initialized.clone();
 
         let (sender, stopped_rx) = unbounded::<()>();
         self.process_stopped_sender = Some(sender);
+        self.generation += 1;
+        let generation = self.generation;
+        let crash_sender = self.crash_sender.clone();
 
         let stdin_chan = self.stdin_receiver.clone();
+        let client = self.client.clone();
 
         let resend_resource_subscriptions = self.resend_resource_subscriptions;
+        let track_resource_subscriptions = self.track_resource_subscriptions;
         let client_resource_subscriptions = self.client_resource_subscriptions.clone();
+        let server_lists = self.server_lists.clone();
+        let client_capabilities = self.client_capabilities.clone();
+        let replay_in_flight_requests = self.replay_in_flight_requests;
+        let replayed_requests = self.replayed_requests.clone();
 
         eprintln!("Starting thread to process IO");
-        thread::spawn(move || {
+        let proxy_thread = thread::spawn(move || {
             let stdin_chan = stdin_chan.lock().unwrap();
 
             // phase 1: initialization
-            let mut init_req = init_req.lock().unwrap();
-            let mut received_client_initialize = false;
-            if init_req.is_none() {
-                eprintln!("Waiting for input to initialize");
-                received_client_initialize = true;
-                match stdin_chan.recv() {
-                    Ok(line) => {
-                        *init_req = Some(line);
+            // client that has not received initialize response yet, f.e because previous
+            // server was stopped before responding, receives the one of this server
+            let answer_client = !*client_initialized.lock().unwrap();
+            if server.initialize_response.is_none() {
+                let cached_init_req = init_req.lock().unwrap().clone();
+                let line = match cached_init_req {
+                    Some(line) => line,
+                    None => {
+                        eprintln!("Waiting for input to initialize");
+                        select! {
+                            recv(stopped_rx) -> _ => {
+                                eprintln!("stopped before client initialized");
+                                server.stop();
+                                return;
+                            }
+                            recv(stdin_chan) -> line => match line {
+                                Ok(line) => {
+                                    *init_req.lock().unwrap() = Some(line.clone());
+                                    line
+                                }
+                                Err(_) => {
+                                    eprintln!("Client has disconnected before initializing");
+                                    server.stop();
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                };
+
+                match server.initialize(&line, &stopped_rx) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        eprintln!("stopped before server initialized");
+                        drop(stdin_chan);
+                        server.stop();
+                        return;
                     }
                     Err(e) => {
-                        eprintln!("{}", e);
+                        eprintln!("Server failed to initialize: {:#}", e);
+                        if answer_client {
+                            // client would wait for response forever otherwise, and
+                            // restarted server has to wait for client to initialize again
+                            *init_req.lock().unwrap() = None;
+                            if let Ok(Frame::Single(Message::Request { id, .. })) =
+                                jsonrpc::parse(&line)
+                            {
+                                client.send(&jsonrpc::error_response(
+                                    &id,
+                                    jsonrpc::SERVER_ERROR,
+                                    &format!("Server failed to initialize: {:#}", e),
+                                ));
+                            }
+                        }
+                        // server that hangs or rejects initialize is as unusable as crashed one
+                        let crash = server.crash(generation).unwrap_or_else(|| Crash {
+                            generation,
+                            reason: format!("failed to initialize: {:#}", e),
+                            uptime: server.started.elapsed(),
+                        });
+                        drop(stdin_chan);
+                        server.stop();
+                        let _ = crash_sender.send(crash);
                         return;
                     }
                 }
             }
 
-            if let Some(line) = &*init_req {
-                process_input
-                    .write_all(line.as_bytes())
-                    .context("failed to write to process stdin")
-                    .unwrap();
-                process_input
-                    .write_all(b"\n")
-                    .context("failed to write to process stdin")
-                    .unwrap();
-                process_input
-                    .flush()
-                    .context("failed to flush process stdin")
-                    .unwrap();
-            }
-
-            let mut process_out = BufReader::new(process_out);
-            let mut initialize_response = String::new();
-            process_out.read_line(&mut initialize_response).unwrap();
-
-            if received_client_initialize {
+            if answer_client {
                 // send initialization response back to client
-                print!("{}", initialize_response);
+                if let Some(response) = &server.initialize_response {
+                    client.send(response);
+                }
+                *client_initialized.lock().unwrap() = true;
+                *client_capabilities.lock().unwrap() = server.capabilities.clone();
             } else {
+                warn_if_capabilities_changed(
+                    client_capabilities.lock().unwrap().as_ref(),
+                    server.capabilities.as_ref(),
+                );
                 eprintln!("skipping server initialize response");
                 // we do not need to send initialize again, as client has
                 // already received one from us earlier
                 // but we need to imitate client's initialized notification now
-                process_input
-                    .write(
-                        r####"{"method":"notifications/initialized","jsonrpc":"2.0"}
-"####
-                            .as_bytes(),
-                    )
-                    .unwrap();
-
-                // we could check here if list of tools actually changed before sending notification
-                // , however possibility of this optimisation to misfire probably outweights its value
-                //                 process_input
-                //                     .write_all(
-                //                         r####"{"method":"tools/list","jsonrpc":"2.0","params":{}}
-                // "####
-                //                             .as_bytes(),
-                //                     )
-                //                     .unwrap();
-
-                // let mut tools_response = String::new();
-                // process_out.read_line(&mut tools_response).unwrap();
-
-                println!(
-                    "{}",
-                    r####"{"method":"notifications/tools/list_changed","jsonrpc":"2.0"}"####
-                );
+                server.send(r####"{"method":"notifications/initialized","jsonrpc":"2.0"}"####);
+            }
 
-                println!(
-                    "{}",
-                    r####"{"method":"notifications/prompts/list_changed","jsonrpc":"2.0"}"####
-                );
+            // phase 2: proxying
+            server.route_output(client.clone());
 
-                println!(
-                    "{}",
-                    r####"{"method":"notifications/resources/list_changed","jsonrpc":"2.0"}"####
+            if !answer_client {
+                server.resume(
+                    client.as_ref(),
+                    resend_resource_subscriptions.then_some(&*client_resource_subscriptions),
+                    &server_lists,
+                    client_capabilities.lock().unwrap().as_ref(),
                 );
+            }
 
-                for (_, subscription) in client_resource_subscriptions.lock().unwrap().iter() {
-                    process_input
-                        .write_all(subscription.as_bytes())
-                        .context("failed to write to process stdin")
-                        .unwrap();
-
-                    // skip the responses, since client does not need them
-                    let mut dummy_buffer = String::new();
-                    process_out.read_line(&mut dummy_buffer).unwrap();
+            for request in replayed_requests.lock().unwrap().drain(..) {
+                eprintln!("Replaying request interrupted by restart");
+                if let Ok(Frame::Single(message)) = jsonrpc::parse(&request) {
+                    server.in_flight.track_client_message(&message);
                 }
+                server.send(&request);
             }
 
-            // phase 2: proxying
-            thread::spawn(move || {
-                eprintln!("started stdout processing");
-                for line in process_out.lines() {
-                    match line {
-                        Ok(line) => {
-                            println!("{}", line);
+            // lists of the first server are queried once client has initialized it,
+            // so that the next server has something to compare its lists with
+            let mut query_lists = answer_client;
+            let exit_check = crossbeam_channel::tick(EXIT_CHECK_INTERVAL);
+            let mut crash = None;
+            let disconnected = crossbeam_channel::never();
+            let mut client_input: &Receiver<String> = &stdin_chan;
+            let mut broken = server.broken.clone();
+            loop {
+                select! {
+                    recv(broken) -> reason => {
+                        let Ok(reason) = reason else {
+                            // output has ended without problems
+                            broken = crossbeam_channel::never();
+                            continue;
+                        };
+                        eprintln!("exiting input processing loop as server has to be stopped");
+                        crash = Some(Crash {
+                            generation,
+                            reason,
+                            uptime: server.started.elapsed(),
+                        });
+                        break;
+                    }
+                    recv(exit_check) -> _ => {
+                        crash = server.crash(generation);
+                        if crash.is_some() {
+                            eprintln!("exiting input processing loop as server process has exited");
+                            break;
                         }
-                        Err(e) => {
-                            eprintln!("{}", e);
-                            eprintln!("ended stdout processing");
-                            return;
+                        if client_input.same_channel(&disconnected)
+                            && !server.in_flight.has_client_requests()
+                        {
+                            eprintln!("exiting input processing loop as client has disconnected");
+                            break;
                         }
                     }
-                }
-                eprintln!("exit ended stdout processing");
-            });
-
-            loop {
-                select! {
                     recv(stopped_rx) -> _ => {
                         eprintln!("exiting input processing loop by rx");
                         break;
                     }
-                    recv(stdin_chan) -> line => {
+                    recv(client_input) -> line => {
                         match line {
-                            Ok(line) => {
-                                if resend_resource_subscriptions {
-                                    if line.contains("resources/subscribe") || line.contains("resources/unsubscribe") {
-                                        let partial: serde_json::Result<PartialJsonRequest> = serde_json::from_str(&line);
-                                        if let Ok(partial) = partial {
-                                            let mut client_resource_subscriptions = client_resource_subscriptions.lock().unwrap();
-                                            if partial.method == "resources/subscribe" {
-                                                client_resource_subscriptions.insert(partial.params.uri.clone(), line.clone());
-                                            } else if partial.method == "resources/unsubscribe" {
-                                                client_resource_subscriptions.remove(&partial.params.uri);
-                                            }
-                                        }
+                            Ok(line) => match jsonrpc::parse(&line) {
+                                Ok(frame) => {
+                                    if track_resource_subscriptions {
+                                        track_subscriptions(&client_resource_subscriptions, &frame);
+                                    }
+                                    if let Some(line) = server.in_flight.track_client_frame(&line, &frame) {
+                                        server.send(&line);
+                                    }
+                                    if query_lists && is_initialized_notification(&frame) {
+                                        query_lists = false;
+                                        *server_lists.lock().unwrap() = Some(server.lists());
                                     }
                                 }
-                                process_input
-                                .write_all(line.as_bytes())
-                                .context("failed to write to process stdin")
-                                .unwrap();
-                                process_input
-                                    .write_all(b"\n")
-                                    .context("failed to write to process stdin")
-                                    .unwrap();
-                                process_input
-                                    .flush()
-                                    .context("failed to flush process stdin")
-                                    .unwrap();
-                            }
-                            Err(e)=>{
-                                eprintln!("Failed to get from chan {}", e);
-                                break;
+                                Err(e) => {
+                                    eprintln!("Forwarding invalid message from client ({:#}): {}", e, line);
+                                    server.send(&line);
+                                }
+                            },
+                            Err(_) => {
+                                // client might still read responses to requests
+                                // it has sent, until synf is shut down
+                                eprintln!("Client has disconnected");
+                                client_input = &disconnected;
                             }
                         }
                     }
                 }
             }
 
-            drop(process_input);
+            let in_flight = server.in_flight.clone();
+            // next server can take client messages while this one is being stopped
+            drop(stdin_chan);
+            server.stop();
 
-            // give it some time to close before killing
-            thread::sleep(Duration::from_secs(2));
+            // server would never respond to requests that are still in flight
+            let error = match &crash {
+                Some(crash) => format!(
+                    "Server process {} before responding to request",
+                    crash.reason
+                ),
+                None => "Server was restarted by synf before responding to request".to_string(),
+            };
+            for (id, request) in in_flight.take_client_requests() {
+                // request that crashed server might crash the next one as well
+                if replay_in_flight_requests && crash.is_none() {
+                    replayed_requests.lock().unwrap().push(request);
+                } else {
+                    client.send(&jsonrpc::error_response(&id, jsonrpc::SERVER_ERROR, &error));
+                }
+            }
 
-            Self::stop(&mut process);
+            if let Some(crash) = crash {
+                let _ = crash_sender.send(crash);
+            }
 
             eprintln!("Finished proxying");
         });
-
-        Ok(())
+        self.proxy_thread = Some(proxy_thread);
     }
 
     // pub(crate) fn stop(self) {
@@ -490,13 +1338,554 @@ impl Runner {
     // }
 }
 
-#[derive(Deserialize)]
-struct PartialJsonRequest {
-    pub method: String,
-    pub params: SubscribeParams,
+fn is_initialized_notification(frame: &Frame) -> bool {
+    matches!(
+        frame,
+        Frame::Single(Message::Notification { method, .. }) if method == "notifications/initialized"
+    )
+}
+
+/// Client cannot renegotiate capabilities without reconnecting,
+/// so changes in them would not take effect until client restarts.
+fn warn_if_capabilities_changed(original: Option<&Value>, current: Option<&Value>) {
+    let (Some(original), Some(current)) = (original, current) else {
+        return;
+    };
+    let differences = capabilities::differences(original, current);
+    if differences.is_empty() {
+        return;
+    }
+    eprintln!("==================================================================");
+    eprintln!("WARNING: restarted server declares different capabilities than");
+    eprintln!("the ones client has received during initialization:");
+    for difference in differences {
+        eprintln!("  - capability {}", difference);
+    }
+    eprintln!("Client cannot renegotiate capabilities, restart it to apply them.");
+    eprintln!("==================================================================");
+}
+
+/// What to do when reload is requested.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Reload {
+    /// Rebuild and restart server.
+    Full,
+    /// Only run build command, f.e to check that it passes.
+    BuildOnly,
+    /// Restart server without building it.
+    RestartOnly,
+}
+
+/// One of commands that build runs.
+struct BuildStep {
+    /// Name of the step, none for the only build command.
+    name: Option<String>,
+    command: String,
+    args: Vec<String>,
+    dir: PathBuf,
+    shell: bool,
+    env: EnvConfig,
+    /// Build goes on even if this command fails.
+    continue_on_error: bool,
+}
+
+impl BuildStep {
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("step {:?}", name),
+            None => "command".to_string(),
+        }
+    }
+}
+
+/// How synf talks to the server started by run command.
+#[derive(PartialEq)]
+enum ServerTransport {
+    Stdio,
+    Http(Url),
+    Sse(Url),
+}
+
+/// Spawned server process with connection to it.
+pub(crate) struct ServerProcess {
+    child: Child,
+    input: Box<dyn ServerInput>,
+    output: Receiver<String>,
+    /// Why server has to be stopped although it is running, see [transport::Connection].
+    broken: Receiver<String>,
+    own_requests: OwnRequests,
+    in_flight: InFlight,
+    /// Messages that server sent before responding to initialize,
+    /// to be forwarded to client once output is routed.
+    early_output: Vec<String>,
+    initialize_timeout: Duration,
+    shutdown_grace: Duration,
+    kill_timeout: Duration,
+    /// Response of the server to cached initialize request,
+    /// set once server was initialized.
+    initialize_response: Option<String>,
+    /// Capabilities declared by server in its initialize response.
+    capabilities: Option<Value>,
+    started: Instant,
+}
+
+/// Server process that exited on its own while synf was proxying it,
+/// or that could not be initialized.
+struct Crash {
+    /// Generation of the server, crashes of already replaced servers are ignored.
+    generation: u64,
+    /// What happened to server, f.e "exited unexpectedly with exit status: 1".
+    reason: String,
+    /// How long server was running before it exited.
+    uptime: Duration,
+}
+
+impl ServerProcess {
+    /// Sends initialize request to server and waits for response with matching id,
+    /// returns none if server was stopped before it responded.
+    fn initialize(
+        &mut self,
+        init_req: &str,
+        stopped: &Receiver<()>,
+    ) -> eyre::Result<Option<String>> {
+        let Ok(Frame::Single(Message::Request { id, .. })) = jsonrpc::parse(init_req) else {
+            return Err(eyre::eyre!("initialize is not a valid JSON-RPC request"));
+        };
+        self.input.send(init_req)?;
+        let timeout = crossbeam_channel::after(self.initialize_timeout);
+        loop {
+            let line = select! {
+                recv(stopped) -> _ => return Ok(None),
+                recv(timeout) -> _ => {
+                    return Err(eyre::eyre!(
+                        "server did not respond to initialize within {:?}",
+                        self.initialize_timeout
+                    ))
+                }
+                recv(self.output) -> line => match line {
+                    Ok(line) => line,
+                    // reason is sent before output is disconnected
+                    Err(_) => match self.broken.try_recv() {
+                        Ok(reason) => return Err(eyre::eyre!("server {}", reason)),
+                        Err(_) => return Err(eyre::eyre!("process exited before responding to initialize")),
+                    },
+                },
+            };
+            match jsonrpc::parse(&line) {
+                Ok(Frame::Single(message)) if message.is_response_to(&id) => {
+                    if let Message::Response {
+                        outcome: Outcome::Error(error),
+                        ..
+                    } = &message
+                    {
+                        return Err(eyre::eyre!(
+                            "server responded to initialize with error {}",
+                            error
+                        ));
+                    }
+                    self.capabilities = capabilities::from_initialize_response(&line);
+                    self.initialize_response = Some(line.clone());
+                    return Ok(Some(line));
+                }
+                Ok(_) => self.early_output.push(line),
+                Err(e) => eprintln!(
+                    "Ignoring invalid message from server before initialize response ({:#}): {}",
+                    e, line
+                ),
+            }
+        }
+    }
+
+    /// Checks whether server process has exited on its own.
+    fn crash(&mut self, generation: u64) -> Option<Crash> {
+        match self.child.try_wait() {
+            Ok(Some(status)) => Some(Crash {
+                generation,
+                reason: format!("exited unexpectedly with {}", status),
+                uptime: self.started.elapsed(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, message: &str) {
+        if let Err(e) = self.input.send(message) {
+            eprintln!("{:#}", e);
+        }
+    }
+
+    /// Starts forwarding server output to client, except for
+    /// responses to requests that synf sent on its own.
+    fn route_output(&mut self, client: Arc<dyn ClientSink>) {
+        let early_output = std::mem::take(&mut self.early_output);
+        let output = self.output.clone();
+        let own_requests = self.own_requests.clone();
+        let in_flight = self.in_flight.clone();
+        thread::spawn(move || {
+            for line in early_output.into_iter().chain(output.iter()) {
+                let frame = match jsonrpc::parse(&line) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        eprintln!("Forwarding invalid message from server ({:#}): {}", e, line);
+                        client.send(&line);
+                        continue;
+                    }
+                };
+                if let Frame::Single(message) = &frame {
+                    if own_requests.deliver(message) {
+                        continue;
+                    }
+                }
+                for message in frame.messages() {
+                    in_flight.track_server_message(message);
+                }
+                client.send(&line);
+            }
+        });
+    }
+
+    /// Sends request on behalf of synf and waits for its response,
+    /// only works once output is routed.
+    fn request(&mut self, method: &str, params: Value) -> eyre::Result<Value> {
+        let (id, response) = self.own_requests.register();
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let sent = self.input.send(&message.to_string());
+        let response = sent.and_then(|_| {
+            response
+                .recv_timeout(OWN_REQUEST_TIMEOUT)
+                .map_err(|_| eyre::eyre!("server did not respond to {} in time", method))
+        });
+        self.own_requests.forget(&id);
+        response
+    }
+
+    /// Brings restarted server to the state that client expects from it,
+    /// resending subscriptions if they are given, and notifies client
+    /// about lists that differ from the ones of previous server.
+    fn resume(
+        &mut self,
+        client: &dyn ClientSink,
+        subscriptions: Option<&Mutex<HashMap<String, Value>>>,
+        server_lists: &Mutex<Option<Lists>>,
+        client_capabilities: Option<&Value>,
+    ) {
+        if let Some(subscriptions) = subscriptions {
+            // resent on behalf of synf, since client does not need the responses
+            let subscriptions: Vec<Value> =
+                subscriptions.lock().unwrap().values().cloned().collect();
+            for params in subscriptions {
+                if let Err(e) = self.request("resources/subscribe", params) {
+                    eprintln!("Failed to resend resource subscription: {:#}", e);
+                }
+            }
+        }
+
+        // notify client only about lists that differ from previous server,
+        // so that it would not refetch everything after every change
+        let current = self.lists();
+        let mut server_lists = server_lists.lock().unwrap();
+        for notification in
+            lists::changed_notifications(server_lists.as_ref(), &current, client_capabilities)
+        {
+            client.send(notification);
+        }
+        *server_lists = Some(current);
+    }
+
+    fn lists(&mut self) -> Lists {
+        let capabilities = self.capabilities.clone();
+        lists::query(
+            &mut |method, params| self.request(method, params),
+            capabilities.as_ref(),
+        )
+    }
+
+    fn stop(self) {
+        let ServerProcess {
+            mut child,
+            input,
+            shutdown_grace,
+            kill_timeout,
+            ..
+        } = self;
+        eprintln!("Closing connection to running process");
+        drop(input);
+        // give it some time to exit on its own before terminating
+        let started = Instant::now();
+        while started.elapsed() < shutdown_grace {
+            if !matches!(child.try_wait(), Ok(None)) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        process::stop_group(&mut child, kill_timeout);
+    }
+}
+
+/// Requests that synf sends to server on its own, which responses must not reach client.
+#[derive(Clone, Default)]
+struct OwnRequests {
+    counter: Arc<AtomicU64>,
+    pending: Arc<Mutex<HashMap<String, Sender<Value>>>>,
+}
+
+impl OwnRequests {
+    const ID_PREFIX: &'static str = "synf-";
+
+    fn register(&self) -> (String, Receiver<Value>) {
+        let id = format!(
+            "{}{}",
+            Self::ID_PREFIX,
+            self.counter.fetch_add(1, Ordering::SeqCst)
+        );
+        let (sender, receiver) = unbounded();
+        self.pending.lock().unwrap().insert(id.clone(), sender);
+        (id, receiver)
+    }
+
+    fn forget(&self, id: &str) {
+        self.pending.lock().unwrap().remove(id);
+    }
+
+    /// Delivers response to waiting request, returns false if message is not one.
+    fn deliver(&self, message: &Message) -> bool {
+        let Message::Response { id, .. } = message else {
+            return false;
+        };
+        let Some(id) = id.as_str() else {
+            return false;
+        };
+        let Some(waiting) = self.pending.lock().unwrap().remove(id) else {
+            // late response to own request that timed out is not for client either
+            return id.starts_with(Self::ID_PREFIX);
+        };
+        let _ = waiting.send(message.to_value());
+        true
+    }
+}
+
+/// Requests between client and running server that are waiting for responses.
+#[derive(Clone, Default)]
+struct InFlight {
+    /// Client requests by serialized id, with their id and original message.
+    client_requests: Arc<Mutex<HashMap<String, (Value, String)>>>,
+    /// Serialized ids of requests that server sent to client.
+    server_requests: Arc<Mutex<HashSet<String>>>,
+}
+
+impl InFlight {
+    /// Tracks messages from client, returns line to send to server without responses
+    /// to requests of the server that is not running anymore, if anything is left.
+    fn track_client_frame(&self, line: &str, frame: &Frame) -> Option<String> {
+        let messages = frame.messages();
+        let forwarded: Vec<&Message> = messages
+            .iter()
+            .filter(|message| self.track_client_message(message))
+            .collect();
+        if forwarded.len() == messages.len() {
+            Some(line.to_string())
+        } else if forwarded.is_empty() {
+            None
+        } else {
+            let batch: Vec<Value> = forwarded.iter().map(|message| message.to_value()).collect();
+            Some(Value::Array(batch).to_string())
+        }
+    }
+
+    /// Tracks message from client, returns false if it must not be sent to server.
+    fn track_client_message(&self, message: &Message) -> bool {
+        match message {
+            Message::Request { id, .. } => {
+                self.client_requests.lock().unwrap().insert(
+                    jsonrpc::id_key(id),
+                    (id.clone(), message.to_value().to_string()),
+                );
+                true
+            }
+            Message::Notification { method, params } => {
+                if method == "notifications/cancelled" {
+                    if let Some(cancelled) = params.as_ref().and_then(|p| p.get("requestId")) {
+                        let cancelled = jsonrpc::id_key(cancelled);
+                        self.client_requests.lock().unwrap().remove(&cancelled);
+                    }
+                }
+                true
+            }
+            Message::Response { id, .. } => {
+                let key = jsonrpc::id_key(id);
+                if !self.server_requests.lock().unwrap().remove(&key) {
+                    eprintln!(
+                        "Dropping client response with id {}, as request was sent by previous server",
+                        id
+                    );
+                    return false;
+                }
+                true
+            }
+        }
+    }
+
+    fn track_server_message(&self, message: &Message) {
+        match message {
+            Message::Request { id, .. } => {
+                self.server_requests
+                    .lock()
+                    .unwrap()
+                    .insert(jsonrpc::id_key(id));
+            }
+            Message::Response { id, .. } => {
+                self.client_requests
+                    .lock()
+                    .unwrap()
+                    .remove(&jsonrpc::id_key(id));
+            }
+            Message::Notification { .. } => {}
+        }
+    }
+
+    fn has_client_requests(&self) -> bool {
+        !self.client_requests.lock().unwrap().is_empty()
+    }
+
+    fn take_client_requests(&self) -> Vec<(Value, String)> {
+        let mut client_requests = self.client_requests.lock().unwrap();
+        client_requests
+            .drain()
+            .map(|(_, request)| request)
+            .collect()
+    }
+}
+
+/// Remembers resources that client subscribed to, to resend subscriptions after restart.
+fn track_subscriptions(subscriptions: &Mutex<HashMap<String, Value>>, frame: &Frame) {
+    for message in frame.messages() {
+        let Message::Request {
+            method,
+            params: Some(params),
+            ..
+        } = message
+        else {
+            continue;
+        };
+        let Some(uri) = params.get("uri").and_then(|uri| uri.as_str()) else {
+            continue;
+        };
+        let mut subscriptions = subscriptions.lock().unwrap();
+        match method.as_str() {
+            "resources/subscribe" => {
+                subscriptions.insert(uri.to_string(), params.clone());
+            }
+            "resources/unsubscribe" => {
+                subscriptions.remove(uri);
+            }
+            _ => {}
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct SubscribeParams {
-    pub uri: String,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Server that only lists given tools.
+    struct FakeServer {
+        tools: Vec<&'static str>,
+        output: Sender<String>,
+    }
+
+    impl ServerInput for FakeServer {
+        fn send(&mut self, message: &str) -> eyre::Result<()> {
+            let request: Value = serde_json::from_str(message)?;
+            let tools: Vec<Value> = self
+                .tools
+                .iter()
+                .map(|name| json!({"name": name}))
+                .collect();
+            let response = match request["method"].as_str() {
+                Some("tools/list") => json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "result": {"tools": tools},
+                }),
+                _ => json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "error": {"code": -32601, "message": "method not found"},
+                }),
+            };
+            let _ = self.output.send(response.to_string());
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Sent(Mutex<Vec<String>>);
+
+    impl ClientSink for Sent {
+        fn send(&self, message: &str) {
+            self.0.lock().unwrap().push(message.to_string());
+        }
+    }
+
+    fn fake_server(tools: &[&'static str], capabilities: &Value) -> ServerProcess {
+        let (output_sender, output) = unbounded();
+        // any process would do, as fake server does not talk over its stdio
+        let child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        ServerProcess {
+            child,
+            input: Box::new(FakeServer {
+                tools: tools.to_vec(),
+                output: output_sender,
+            }),
+            output,
+            broken: crossbeam_channel::never(),
+            own_requests: OwnRequests::default(),
+            in_flight: InFlight::default(),
+            early_output: Vec::new(),
+            initialize_timeout: Duration::from_secs(1),
+            shutdown_grace: Duration::ZERO,
+            kill_timeout: Duration::ZERO,
+            initialize_response: None,
+            capabilities: Some(capabilities.clone()),
+            started: Instant::now(),
+        }
+    }
+
+    /// Messages that client receives when server with previous tools
+    /// is restarted with current ones.
+    fn restart(previous: &[&'static str], current: &[&'static str]) -> Vec<String> {
+        let capabilities = json!({"tools": {"listChanged": true}});
+        let client = Arc::new(Sent::default());
+        let server_lists = Mutex::new(None);
+
+        let mut server = fake_server(previous, &capabilities);
+        server.route_output(client.clone());
+        *server_lists.lock().unwrap() = Some(server.lists());
+        let _ = server.child.wait();
+
+        let mut server = fake_server(current, &capabilities);
+        server.route_output(client.clone());
+        server.resume(client.as_ref(), None, &server_lists, Some(&capabilities));
+        let _ = server.child.wait();
+
+        let sent = client.0.lock().unwrap().clone();
+        sent
+    }
+
+    #[test]
+    fn notifies_client_about_changed_tools_after_restart() {
+        assert_eq!(
+            restart(&["t1"], &["t2"]),
+            vec![r#"{"method":"notifications/tools/list_changed","jsonrpc":"2.0"}"#]
+        );
+    }
+
+    #[test]
+    fn does_not_notify_client_about_unchanged_tools_after_restart() {
+        assert!(restart(&["t1"], &["t1"]).is_empty());
+    }
 }
@@ -0,0 +1,140 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{ChildStdin, ChildStdout},
+    thread,
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use eyre::Context;
+
+use crate::jsonrpc;
+
+/// Sends messages from synf to running server.
+pub(crate) trait ServerInput: Send {
+    /// Sends single JSON-RPC message to server.
+    fn send(&mut self, message: &str) -> eyre::Result<()>;
+}
+
+/// Connection to running server, whichever transport it uses.
+pub(crate) struct Connection {
+    pub(crate) input: Box<dyn ServerInput>,
+    /// Messages received from server, disconnected once server stops sending them.
+    pub(crate) output: Receiver<String>,
+    /// Receives why server has to be stopped although it is still running,
+    /// f.e because of stray output on stdout with strict_stdout.
+    pub(crate) broken: Receiver<String>,
+}
+
+/// What to do with lines that server prints to stdout, which are not JSON-RPC messages.
+#[derive(Clone)]
+pub(crate) struct StdoutPolicy {
+    /// Describes process in warnings, f.e by its command and pid.
+    pub(crate) process: String,
+    /// Stop reading server stdout at first such line, instead of rerouting it to stderr.
+    pub(crate) strict: bool,
+    /// Send warnings to client as log messages too.
+    pub(crate) notify_client: bool,
+}
+
+struct Stdin(ChildStdin);
+
+impl ServerInput for Stdin {
+    fn send(&mut self, message: &str) -> eyre::Result<()> {
+        self.0
+            .write_all(message.as_bytes())
+            .context("failed to write to process stdin")?;
+        self.0
+            .write_all(b"\n")
+            .context("failed to write to process stdin")?;
+        self.0.flush().context("failed to flush process stdin")
+    }
+}
+
+/// Connects to server talking over its stdio.
+pub(crate) fn stdio(input: ChildStdin, output: ChildStdout, policy: StdoutPolicy) -> Connection {
+    let (sender, receiver) = unbounded::<String>();
+    let (broken_sender, broken) = unbounded::<String>();
+
+    thread::spawn(move || {
+        eprintln!("started stdout processing");
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    // anything else printed to stdout would corrupt the stream for client
+                    if let Err(e) = jsonrpc::parse(&line) {
+                        if reroute(&policy, &line, e, &sender) {
+                            continue;
+                        }
+                        if policy.strict {
+                            let _ = broken_sender.send(
+                                "printed to stdout something that is not JSON-RPC message with strict_stdout set"
+                                    .to_string(),
+                            );
+                        }
+                        break;
+                    }
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    eprintln!("ended stdout processing");
+                    return;
+                }
+            }
+        }
+        eprintln!("exit ended stdout processing");
+    });
+
+    Connection {
+        input: Box::new(Stdin(input)),
+        output: receiver,
+        broken,
+    }
+}
+
+/// Keeps line that is not JSON-RPC message away from client,
+/// returns false if server output must not be read anymore.
+fn reroute(
+    policy: &StdoutPolicy,
+    line: &str,
+    error: eyre::Report,
+    sender: &Sender<String>,
+) -> bool {
+    if policy.strict {
+        eprintln!("--------------------------------------------------------------");
+        eprintln!("ERROR: {} printed to stdout", policy.process);
+        eprintln!("something that is not JSON-RPC message ({:#}):", error);
+        eprintln!("{}", line);
+        eprintln!("Stdout is reserved for MCP messages, use stderr for logs.");
+        eprintln!("This process is stopped, as strict_stdout is set.");
+        eprintln!("--------------------------------------------------------------");
+        if policy.notify_client {
+            let message = format!(
+                "{} printed non JSON-RPC output to stdout, it is stopped: {}",
+                policy.process, line
+            );
+            let _ = sender.send(jsonrpc::log_notification("error", &message));
+        }
+        return false;
+    }
+
+    eprintln!(
+        "WARNING: {} printed to stdout something that is not JSON-RPC message, rerouted to stderr: {}",
+        policy.process, line
+    );
+    if policy.notify_client {
+        let message = format!(
+            "{} printed non JSON-RPC output to stdout: {}",
+            policy.process, line
+        );
+        return sender
+            .send(jsonrpc::log_notification("warning", &message))
+            .is_ok();
+    }
+    true
+}
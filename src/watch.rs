@@ -0,0 +1,629 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use eyre::Context;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::{gitignore::Gitignore, gitignore::GitignoreBuilder, Match};
+use notify_debouncer_full::{
+    new_debouncer, new_debouncer_opt,
+    notify::{
+        event::{AccessKind, AccessMode},
+        Config, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode,
+    },
+    DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache,
+};
+
+use crate::{
+    config::{CommandConfig, Watch, WatchAction, WatchMode, WatchRule},
+    control,
+};
+
+/// Watches paths with native watcher, falling back to polling
+/// for paths that native one could not watch.
+pub(crate) struct Watcher {
+    mode: WatchMode,
+    debounce: Duration,
+    poll_interval: Duration,
+    events: Sender<DebounceEventResult>,
+    native: Option<Debouncer<RecommendedWatcher, RecommendedCache>>,
+    poll: Option<Debouncer<PollWatcher, RecommendedCache>>,
+}
+
+impl Watcher {
+    /// Creates watcher that sends debounced batches of events to given channel.
+    pub(crate) fn new(
+        watch: Option<&Watch>,
+        debounce: Duration,
+        events: Sender<DebounceEventResult>,
+    ) -> Watcher {
+        let poll_interval = watch.and_then(|watch| watch.poll_interval_ms);
+        Watcher {
+            mode: watch
+                .and_then(|watch| watch.mode.clone())
+                .unwrap_or_default(),
+            debounce,
+            poll_interval: Duration::from_millis(poll_interval.unwrap_or(1000)),
+            events,
+            native: None,
+            poll: None,
+        }
+    }
+
+    pub(crate) fn watch(&mut self, path: &Path) -> eyre::Result<()> {
+        if !path.exists() {
+            // polling would not help with that
+            return Err(eyre::eyre!("path does not exist"));
+        }
+        if self.mode == WatchMode::Native {
+            match self.watch_natively(path) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    eprintln!(
+                        "Warning: failed to watch {:?} natively, falling back to polling every {:?}: {:#}",
+                        path, self.poll_interval, e
+                    );
+                }
+            }
+        }
+        self.poll(path)
+    }
+
+    fn watch_natively(&mut self, path: &Path) -> eyre::Result<()> {
+        let native = match &mut self.native {
+            Some(native) => native,
+            None => {
+                let events = self.events.clone();
+                let native = new_debouncer(self.debounce, None, move |result| {
+                    let _ = events.send(result);
+                })
+                .context("failed to create native watcher")?;
+                self.native.insert(native)
+            }
+        };
+        native.watch(path, RecursiveMode::Recursive)?;
+        Ok(())
+    }
+
+    fn poll(&mut self, path: &Path) -> eyre::Result<()> {
+        let poll = match &mut self.poll {
+            Some(poll) => poll,
+            None => {
+                let events = self.events.clone();
+                let poll = new_debouncer_opt::<_, PollWatcher, _>(
+                    self.debounce,
+                    None,
+                    move |result| {
+                        let _ = events.send(result);
+                    },
+                    RecommendedCache::new(),
+                    Config::default().with_poll_interval(self.poll_interval),
+                )
+                .context("failed to create polling watcher")?;
+                self.poll.insert(poll)
+            }
+        };
+        poll.watch(path, RecursiveMode::Recursive)?;
+        Ok(())
+    }
+}
+
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Decides which changes in watched paths should reload server.
+pub(crate) struct Filter {
+    /// Project directory as given, which is how watcher reports paths.
+    root: PathBuf,
+    canonical_root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    gitignore: bool,
+    /// Ignore files of directories, loaded once they are needed.
+    ignores: HashMap<PathBuf, Option<Gitignore>>,
+    /// Paths relative to project that are never filtered out.
+    kept: Vec<PathBuf>,
+}
+
+impl Filter {
+    /// Default include and exclude of the language are used when [watch] does not set them.
+    pub(crate) fn new(
+        root: &Path,
+        watch: Option<&Watch>,
+        default_include: &[String],
+        default_exclude: &[String],
+    ) -> eyre::Result<Filter> {
+        let include = match watch.and_then(|watch| watch.include.as_ref()) {
+            Some(patterns) => Some(glob_set(patterns).context("invalid include in [watch]")?),
+            None if default_include.is_empty() => None,
+            None => Some(glob_set(default_include)?),
+        };
+        let exclude = watch.and_then(|watch| watch.exclude.as_ref());
+        let exclude = glob_set(exclude.map(Vec::as_slice).unwrap_or(default_exclude))
+            .context("invalid exclude in [watch]")?;
+        Ok(Filter {
+            root: root.to_path_buf(),
+            canonical_root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+            include,
+            exclude,
+            gitignore: watch.and_then(|watch| watch.gitignore).unwrap_or(false),
+            ignores: HashMap::new(),
+            kept: Vec::new(),
+        })
+    }
+
+    /// Makes changes of the path in project never filtered out, f.e of env files,
+    /// which are usually ignored by git.
+    pub(crate) fn keep(&mut self, path: &Path) {
+        let relative = relative(&self.root, &self.canonical_root, &self.root.join(path));
+        self.kept.push(relative);
+    }
+
+    /// Returns changed paths from the batch of events that are not filtered out,
+    /// relative to project if they are in it.
+    pub(crate) fn changed(&mut self, events: &[DebouncedEvent]) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for event in events {
+            if !is_change(&event.kind) {
+                continue;
+            }
+            for path in &event.paths {
+                if IGNORE_FILES.iter().any(|name| path.ends_with(name)) {
+                    // ignore rules might have changed
+                    self.ignores.clear();
+                }
+                let relative = relative(&self.root, &self.canonical_root, path);
+                if !changed.contains(&relative) && self.matches(&relative) {
+                    eprintln!("Changed {:?}", path);
+                    changed.push(relative);
+                }
+            }
+        }
+        changed
+    }
+
+    fn matches(&mut self, relative: &Path) -> bool {
+        if self.kept.iter().any(|kept| kept == relative) {
+            return true;
+        }
+        // socket of synf itself is created in project folder
+        if relative == Path::new(control::SOCKET_NAME) {
+            return false;
+        }
+        if self.exclude.is_match(relative) {
+            return false;
+        }
+        if let Some(include) = &self.include {
+            if !include.is_match(relative) {
+                return false;
+            }
+        }
+        // paths outside of project, f.e from extra_paths, are not in its ignore files
+        !(self.gitignore && relative.is_relative() && self.is_ignored(relative))
+    }
+
+    /// Checks ignore files in all directories from project root to the path,
+    /// where the deepest one that has a rule for the path decides.
+    fn is_ignored(&mut self, relative: &Path) -> bool {
+        if relative.starts_with(".git") {
+            return true;
+        }
+
+        let path = self.canonical_root.join(relative);
+        let is_dir = path.is_dir();
+        let mut ignored = false;
+        let mut dir = self.canonical_root.clone();
+        let mut components = relative.components().peekable();
+        loop {
+            if let Some(gitignore) = self.ignore_of(&dir) {
+                match gitignore.matched_path_or_any_parents(&path, is_dir) {
+                    Match::Ignore(_) => ignored = true,
+                    Match::Whitelist(_) => ignored = false,
+                    Match::None => {}
+                }
+            }
+            match components.next() {
+                Some(component) if components.peek().is_some() => dir.push(component),
+                _ => break,
+            }
+        }
+        ignored
+    }
+
+    fn ignore_of(&mut self, dir: &Path) -> Option<&Gitignore> {
+        self.ignores
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let mut builder = GitignoreBuilder::new(dir);
+                let mut found = false;
+                // later files take precedence, so .ignore goes last
+                for name in IGNORE_FILES {
+                    let file = dir.join(name);
+                    if file.is_file() {
+                        found = true;
+                        if let Some(e) = builder.add(&file) {
+                            eprintln!("Failed to read {:?}: {}", file, e);
+                        }
+                    }
+                }
+                if !found {
+                    return None;
+                }
+                builder
+                    .build()
+                    .inspect_err(|e| eprintln!("Invalid ignore file in {:?}: {}", dir, e))
+                    .ok()
+            })
+            .as_ref()
+    }
+}
+
+/// What has to be done about changes, which is the strongest action
+/// of rules matching changed paths.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Plan {
+    /// URIs of resources that were updated.
+    NotifyResources(Vec<String>),
+    /// Commands to run.
+    Run(Vec<CommandConfig>),
+    Restart,
+    Rebuild,
+}
+
+impl Plan {
+    fn action(&self) -> WatchAction {
+        match self {
+            Plan::NotifyResources(_) => WatchAction::NotifyResources,
+            Plan::Run(_) => WatchAction::Run,
+            Plan::Restart => WatchAction::Restart,
+            Plan::Rebuild => WatchAction::Rebuild,
+        }
+    }
+
+    fn merge(self, other: Plan) -> Plan {
+        match (self, other) {
+            (Plan::NotifyResources(mut uris), Plan::NotifyResources(other)) => {
+                for uri in other {
+                    if !uris.contains(&uri) {
+                        uris.push(uri);
+                    }
+                }
+                Plan::NotifyResources(uris)
+            }
+            (Plan::Run(mut commands), Plan::Run(other)) => {
+                for command in other {
+                    if !commands.contains(&command) {
+                        commands.push(command);
+                    }
+                }
+                Plan::Run(commands)
+            }
+            (plan, other) => {
+                if plan.action() >= other.action() {
+                    plan
+                } else {
+                    other
+                }
+            }
+        }
+    }
+}
+
+/// Maps changed paths to actions, paths that do not match any rule rebuild server.
+pub(crate) struct Rules {
+    root: PathBuf,
+    canonical_root: PathBuf,
+    rules: Vec<(GlobMatcher, WatchRule)>,
+    /// Paths relative to project with actions that take precedence over rules.
+    paths: Vec<(PathBuf, WatchAction)>,
+}
+
+impl Rules {
+    pub(crate) fn new(root: &Path, watch: Option<&Watch>) -> eyre::Result<Rules> {
+        let mut rules = Vec::new();
+        for rule in watch
+            .and_then(|watch| watch.rules.clone())
+            .unwrap_or_default()
+        {
+            let glob = Glob::new(&rule.glob)
+                .with_context(|| format!("invalid glob {:?} in [[watch.rules]]", rule.glob))?;
+            if rule.action == WatchAction::Run && rule.command.command.is_none() {
+                return Err(eyre::eyre!(
+                    "command is required for run action of {:?} in [[watch.rules]]",
+                    rule.glob
+                ));
+            }
+            rules.push((glob.compile_matcher(), rule));
+        }
+        Ok(Rules {
+            root: root.to_path_buf(),
+            canonical_root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+            rules,
+            paths: Vec::new(),
+        })
+    }
+
+    /// Makes changes of the path in project always cause the action, or a stronger one
+    /// if it was already given for the path, f.e restart for env file of run command.
+    pub(crate) fn path(&mut self, path: &Path, action: WatchAction) {
+        let relative = relative(&self.root, &self.canonical_root, &self.root.join(path));
+        match self
+            .paths
+            .iter_mut()
+            .find(|(existing, _)| *existing == relative)
+        {
+            Some((_, existing)) => *existing = action.max(*existing),
+            None => self.paths.push((relative, action)),
+        }
+    }
+
+    /// Plans what to do about paths changed in project, nothing if none have changed.
+    pub(crate) fn plan(&self, changed: &[PathBuf]) -> Option<Plan> {
+        changed
+            .iter()
+            .map(|path| self.plan_path(path))
+            .reduce(Plan::merge)
+    }
+
+    fn plan_path(&self, relative: &Path) -> Plan {
+        if let Some((_, action)) = self.paths.iter().find(|(path, _)| path == relative) {
+            // only restart and rebuild are given for paths
+            return match action {
+                WatchAction::Restart => Plan::Restart,
+                _ => Plan::Rebuild,
+            };
+        }
+        let Some((_, rule)) = self.rules.iter().find(|(glob, _)| glob.is_match(relative)) else {
+            return Plan::Rebuild;
+        };
+        match rule.action {
+            WatchAction::NotifyResources => {
+                let absolute = self.canonical_root.join(relative);
+                let uri = rule
+                    .uri
+                    .as_deref()
+                    .unwrap_or("file://{absolute_path}")
+                    .replace("{path}", &slashed(relative))
+                    .replace("{absolute_path}", &slashed(&absolute));
+                Plan::NotifyResources(vec![uri])
+            }
+            WatchAction::Run => Plan::Run(vec![rule.command.clone()]),
+            WatchAction::Restart => Plan::Restart,
+            WatchAction::Rebuild => Plan::Rebuild,
+        }
+    }
+}
+
+/// Path relative to project if it is in it, as watcher reports paths joined
+/// with the project path as given.
+fn relative(root: &Path, canonical_root: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(root)
+        .or_else(|_| path.strip_prefix(canonical_root))
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// Path with forward slashes, as they are used in URIs.
+fn slashed(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if path.starts_with('/') {
+        path
+    } else if path.contains(':') {
+        // windows absolute path, f.e C:/project
+        format!("/{}", path)
+    } else {
+        path
+    }
+}
+
+/// Applies plan for every batch of changes, where all batches that come
+/// while plan is applied are coalesced into a single follow-up plan.
+pub(crate) fn apply_on_changes(
+    events: Receiver<DebounceEventResult>,
+    mut filter: Filter,
+    rules: Rules,
+    mut apply: impl FnMut(Plan),
+) {
+    let mut plan = |result: DebounceEventResult| match result {
+        Ok(events) => rules.plan(&filter.changed(&events)),
+        Err(errors) => {
+            errors.iter().for_each(|error| eprintln!("{error:?}"));
+            None
+        }
+    };
+    while let Ok(result) = events.recv() {
+        let mut coalesced = plan(result);
+        for result in events.try_iter() {
+            coalesced = match (coalesced, plan(result)) {
+                (Some(coalesced), Some(next)) => Some(coalesced.merge(next)),
+                (coalesced, next) => coalesced.or(next),
+            };
+        }
+        if let Some(plan) = coalesced {
+            apply(plan);
+        }
+    }
+}
+
+/// Reading files does not change them, so only writes are interesting among accesses.
+fn is_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+fn glob_set(patterns: &[String]) -> eyre::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("invalid glob {:?}", pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(config: &str) -> Watch {
+        toml::from_str(config).unwrap()
+    }
+
+    const RULES: &str = r#"
+[[rules]]
+glob = "prompts/**"
+action = "notify_resources"
+uri = "prompt://{path}"
+
+[[rules]]
+glob = "notes/**"
+action = "notify_resources"
+
+[[rules]]
+glob = "schema/**"
+action = "run"
+command = "npm"
+args = ["run", "generate"]
+
+[[rules]]
+glob = "data/**"
+action = "restart"
+"#;
+
+    fn rules() -> Rules {
+        Rules::new(Path::new("/project"), Some(&watch(RULES))).unwrap()
+    }
+
+    fn plan(rules: &Rules, changed: &[&str]) -> Option<Plan> {
+        let changed: Vec<PathBuf> = changed.iter().map(PathBuf::from).collect();
+        rules.plan(&changed)
+    }
+
+    fn generate() -> CommandConfig {
+        CommandConfig {
+            command: Some("npm".to_string()),
+            args: Some(vec!["run".to_string(), "generate".to_string()]),
+            cwd: None,
+            shell: None,
+        }
+    }
+
+    #[test]
+    fn plans_action_of_first_matching_rule() {
+        let rules = rules();
+        assert_eq!(plan(&rules, &[]), None);
+        assert_eq!(
+            plan(&rules, &["prompts/a.md"]),
+            Some(Plan::NotifyResources(vec![
+                "prompt://prompts/a.md".to_string()
+            ]))
+        );
+        assert_eq!(
+            plan(&rules, &["schema/api.json"]),
+            Some(Plan::Run(vec![generate()]))
+        );
+        assert_eq!(plan(&rules, &["data/a.csv"]), Some(Plan::Restart));
+        assert_eq!(plan(&rules, &["src/main.py"]), Some(Plan::Rebuild));
+    }
+
+    #[test]
+    fn fills_uri_templates() {
+        assert_eq!(
+            plan(&rules(), &["notes/a b.md"]),
+            Some(Plan::NotifyResources(vec![
+                "file:///project/notes/a b.md".to_string()
+            ]))
+        );
+        assert_eq!(slashed(Path::new("dir/file.md")), "dir/file.md");
+        assert_eq!(
+            slashed(Path::new(r"C:\project\file.md")),
+            "/C:/project/file.md"
+        );
+    }
+
+    #[test]
+    fn merges_plans_into_the_strongest_one() {
+        let rules = rules();
+        assert_eq!(
+            plan(&rules, &["prompts/a.md", "prompts/b.md", "prompts/a.md"]),
+            Some(Plan::NotifyResources(vec![
+                "prompt://prompts/a.md".to_string(),
+                "prompt://prompts/b.md".to_string(),
+            ]))
+        );
+        assert_eq!(
+            plan(&rules, &["prompts/a.md", "schema/a.json", "schema/b.json"]),
+            Some(Plan::Run(vec![generate()]))
+        );
+        assert_eq!(
+            plan(&rules, &["schema/a.json", "data/a.csv", "prompts/a.md"]),
+            Some(Plan::Restart)
+        );
+        assert_eq!(
+            plan(&rules, &["data/a.csv", "src/main.py"]),
+            Some(Plan::Rebuild)
+        );
+    }
+
+    #[test]
+    fn actions_of_paths_take_precedence_over_rules() {
+        let mut rules = rules();
+        rules.path(Path::new("data/.env"), WatchAction::Restart);
+        rules.path(Path::new("prompts/.env"), WatchAction::Rebuild);
+        // weaker action does not replace stronger one given earlier
+        rules.path(Path::new("prompts/.env"), WatchAction::Restart);
+        rules.path(Path::new(".env"), WatchAction::Restart);
+        assert_eq!(plan(&rules, &["data/.env"]), Some(Plan::Restart));
+        assert_eq!(plan(&rules, &["prompts/.env"]), Some(Plan::Rebuild));
+        assert_eq!(plan(&rules, &[".env"]), Some(Plan::Restart));
+    }
+
+    #[test]
+    fn rejects_run_rule_without_command() {
+        let config = watch("[[rules]]\nglob = \"a/**\"\naction = \"run\"\n");
+        assert!(Rules::new(Path::new("/project"), Some(&config)).is_err());
+    }
+
+    fn new_filter(config: &str, default_include: &[&str], default_exclude: &[&str]) -> Filter {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Filter::new(
+            Path::new("/project"),
+            Some(&watch(config)),
+            &strings(default_include),
+            &strings(default_exclude),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_by_include_and_exclude() {
+        let mut filter = new_filter("", &["**/*.cs", "*.sln"], &["**/bin/**", "**/obj/**"]);
+        assert!(filter.matches(Path::new("src/Program.cs")));
+        assert!(filter.matches(Path::new("App.sln")));
+        assert!(!filter.matches(Path::new("src/bin/Debug/Program.cs")));
+        assert!(!filter.matches(Path::new("readme.md")));
+
+        // configured patterns replace the default ones
+        let mut filter = new_filter(
+            "include = [\"**/*.md\"]\nexclude = []",
+            &["**/*.cs"],
+            &["**/bin/**"],
+        );
+        assert!(filter.matches(Path::new("bin/readme.md")));
+        assert!(!filter.matches(Path::new("src/Program.cs")));
+    }
+
+    #[test]
+    fn keeps_kept_paths_and_ignores_control_socket() {
+        let mut filter = new_filter("exclude = [\".env*\"]", &[], &[]);
+        assert!(filter.matches(Path::new("src/main.py")));
+        assert!(!filter.matches(Path::new(".env.run")));
+        filter.keep(Path::new(".env.run"));
+        assert!(filter.matches(Path::new(".env.run")));
+        assert!(!filter.matches(Path::new(control::SOCKET_NAME)));
+    }
+}